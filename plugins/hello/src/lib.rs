@@ -0,0 +1,155 @@
+// A sample native module for smsh.  Loaded with
+//
+//      $ self::load_module path/to/libhello.so
+//
+// it adds `hello::greet`, `hello::count`, `hello::remember` and
+// `hello::length`.  The declarations below mirror version 1 of the
+// module interface; see doc/modules.md.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+
+type Builtin = extern "C" fn(host: *const Host, argc: c_int, argv: *const *const c_char) -> c_int;
+
+#[repr(C)]
+pub struct Host {
+    version: u32,
+    shell: *mut c_void,
+    rv: extern "C" fn(shell: *mut c_void) -> c_int,
+    get_variable: extern "C" fn(
+        shell: *mut c_void,
+        name: *const c_char,
+        buf: *mut c_char,
+        len: usize,
+    ) -> isize,
+    set_variable:
+        extern "C" fn(shell: *mut c_void, name: *const c_char, value: *const c_char) -> c_int,
+    read: extern "C" fn(shell: *mut c_void, buf: *mut c_void, len: usize) -> isize,
+    write: extern "C" fn(shell: *mut c_void, fd: c_int, buf: *const c_void, len: usize) -> isize,
+}
+
+#[repr(C)]
+pub struct Registrar {
+    version: u32,
+    context: *mut c_void,
+    add_builtin:
+        extern "C" fn(context: *mut c_void, name: *const c_char, builtin: Builtin) -> c_int,
+}
+
+/// # Safety
+///
+/// Called by smsh, with a valid registrar.
+#[no_mangle]
+pub unsafe extern "C" fn smsh_module_register_v1(registrar: *mut Registrar) -> c_int {
+    let registrar = &*registrar;
+
+    let builtins: [(&CStr, Builtin); 4] = [
+        (c"greet", greet),
+        (c"count", count),
+        (c"remember", remember),
+        (c"length", length),
+    ];
+
+    for (name, builtin) in builtins {
+        if (registrar.add_builtin)(registrar.context, name.as_ptr(), builtin) != 0 {
+            return 1;
+        }
+    }
+
+    0
+}
+
+fn args(argc: c_int, argv: *const *const c_char) -> Vec<String> {
+    (0..argc as usize)
+        .map(|i| unsafe { CStr::from_ptr(*argv.add(i)) })
+        .map(|x| x.to_string_lossy().to_string())
+        .collect()
+}
+
+fn write(host: &Host, fd: c_int, text: &str) {
+    (host.write)(host.shell, fd, text.as_ptr() as *const c_void, text.len());
+}
+
+fn get_variable(host: &Host, name: &str) -> Option<String> {
+    let name = CString::new(name).ok()?;
+
+    let len = (host.get_variable)(host.shell, name.as_ptr(), std::ptr::null_mut(), 0);
+    if len < 0 {
+        return None;
+    }
+
+    let mut buf = vec![0u8; len as usize + 1];
+    (host.get_variable)(
+        host.shell,
+        name.as_ptr(),
+        buf.as_mut_ptr() as *mut c_char,
+        buf.len(),
+    );
+    buf.pop();
+
+    String::from_utf8(buf).ok()
+}
+
+// hello::greet [name...]
+// Greets each name given, or else the value of `name`, or else the world
+extern "C" fn greet(host: *const Host, argc: c_int, argv: *const *const c_char) -> c_int {
+    let host = unsafe { &*host };
+    let args = args(argc, argv);
+
+    let names = if args.len() > 1 {
+        args[1..].to_vec()
+    } else {
+        vec![get_variable(host, "name").unwrap_or_else(|| "world".to_string())]
+    };
+
+    for name in names {
+        write(host, 1, &format!("Hello, {}!\n", name));
+    }
+
+    0
+}
+
+// hello::count [arg...]
+// Sets rv to the number of arguments
+extern "C" fn count(_host: *const Host, argc: c_int, _argv: *const *const c_char) -> c_int {
+    argc - 1
+}
+
+// hello::remember name value
+extern "C" fn remember(host: *const Host, argc: c_int, argv: *const *const c_char) -> c_int {
+    let host = unsafe { &*host };
+
+    if argc != 3 {
+        write(host, 2, "hello::remember: Expected a name and a value\n");
+        return 2;
+    }
+
+    unsafe {
+        if (host.set_variable)(host.shell, *argv.add(1), *argv.add(2)) != 0 {
+            write(host, 2, "hello::remember: Unable to set variable\n");
+            return 1;
+        }
+    }
+
+    0
+}
+
+// hello::length name
+// Sets rv to the length of the value of `name`
+extern "C" fn length(host: *const Host, argc: c_int, argv: *const *const c_char) -> c_int {
+    let host = unsafe { &*host };
+    let args = args(argc, argv);
+
+    if args.len() != 2 {
+        write(host, 2, "hello::length: Expected a name\n");
+        return 2;
+    }
+
+    match get_variable(host, &args[1]) {
+        Some(value) => value.len() as c_int,
+        None => {
+            write(host, 2, "hello::length: No such variable\n");
+            255
+        }
+    }
+}
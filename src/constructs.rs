@@ -1,10 +1,13 @@
 use crate::line::Line;
 /// This file contains the definitions for
-/// if, for, while, let and fn.
+/// if, for, while, break, continue, let and fn.
 // Countdown: Return all 10 lines, then 9, then 8...
+use crate::shell::variables::is_special_variable;
 use crate::shell::Shell;
 use crate::sources::r#for::For;
+use crate::sources::r#while::While;
 use crate::sources::user_function::UserFunction;
+use crate::sources::Interrupt;
 
 use anyhow::{anyhow, Result};
 
@@ -24,7 +27,7 @@ pub fn r#if(smsh: &mut Shell, line: &mut Line) -> Result<()> {
 
     let mut bodies = vec![body]; // Vector of conditional bodies,
                                  // We must collect these first, then determine which to execute
-                                 // by executing their conditionals in a subshell environment.
+                                 // by executing their conditionals.
     let mut conditionals = vec![conditional];
 
     while let Some(line) = smsh.get_line()? {
@@ -44,6 +47,7 @@ pub fn r#if(smsh: &mut Shell, line: &mut Line) -> Result<()> {
         if line.is_else() {
             Some(smsh.get_block(line.source(), line.indentation() + 1)?)
         } else {
+            smsh.push_line(line);
             None
         }
     } else {
@@ -53,15 +57,10 @@ pub fn r#if(smsh: &mut Shell, line: &mut Line) -> Result<()> {
     let mut found = false;
 
     for (conditional, body) in conditionals.iter().zip(bodies) {
-        match smsh.evaluate_conditional(conditional)? {
-            Some(b) => {
-                if b {
-                    found = true;
-                    smsh.push_block(body);
-                    break;
-                }
-            }
-            None => return Ok(()),
+        if smsh.evaluate_conditional(conditional, line.source())? {
+            found = true;
+            smsh.push_block(body);
+            break;
         }
     }
 
@@ -75,8 +74,38 @@ pub fn r#if(smsh: &mut Shell, line: &mut Line) -> Result<()> {
 }
 
 // Collect a block of input from the shell, create a
-// a new function with it, and save it into the shell
+// a new function with it, and save it into the shell.
+// `fn --drop [--global] name` removes a function instead.
 pub fn r#fn(smsh: &mut Shell, line: &mut Line) -> Result<()> {
+    let mut argv = line.argv();
+    argv.remove(0);
+
+    let drop = argv.first() == Some(&"--drop");
+    if drop {
+        argv.remove(0);
+    }
+
+    let global = argv.first() == Some(&"--global");
+    if global {
+        argv.remove(0);
+    }
+
+    // A script module's functions are its own, to be unloaded with it
+    if global {
+        if let Some(module) = smsh.state().loading_module() {
+            let module = module.to_string();
+            if !drop {
+                smsh.get_block(line.source(), line.indentation() + 1)?;
+            }
+            smsh.set_rv(2);
+            return Err(anyhow!("fn: --global: Not allowed in module `{}`", module));
+        }
+    }
+
+    if drop {
+        return drop_fn(smsh, argv, global);
+    }
+
     // If function invocation is incorrect, we collect and discard
     // the following block of input
     let fn_body = smsh
@@ -85,19 +114,34 @@ pub fn r#fn(smsh: &mut Shell, line: &mut Line) -> Result<()> {
         .map(|x| x.raw_text().to_string())
         .collect();
 
-    let argv = line.argv();
-
-    if argv.len() != 2 {
+    if argv.len() != 1 {
         smsh.set_rv(-1);
         return Err(anyhow!("fn: Improper invocation of `fn`"));
     }
 
-    let fn_name = argv.last().unwrap().to_string();
+    // The colon ending the definition is not part of the name
+    let fn_name = argv[0].strip_suffix(':').unwrap_or(argv[0]).to_string();
 
     let func = UserFunction::new(fn_name, fn_body);
 
-    smsh.insert_user_function(func);
+    smsh.insert_user_function(func, global);
 
+    smsh.set_rv(0);
+    Ok(())
+}
+
+fn drop_fn(smsh: &mut Shell, argv: Vec<&str>, global: bool) -> Result<()> {
+    if argv.len() != 1 {
+        smsh.set_rv(-1);
+        return Err(anyhow!("fn: Improper invocation of `fn --drop`"));
+    }
+
+    if smsh.remove_user_function(argv[0], global).is_none() {
+        smsh.set_rv(1);
+        return Err(anyhow!("fn: {}: No such function in scope", argv[0]));
+    }
+
+    smsh.set_rv(0);
     Ok(())
 }
 
@@ -136,23 +180,58 @@ pub fn r#for(smsh: &mut Shell, line: &mut Line) -> Result<()> {
     Ok(())
 }
 
+// Define and push a 'while' loop onto execution stack.  The variable
+// `while_limit`, if set, limits the number of passes of each loop.
 pub fn r#while(smsh: &mut Shell, line: &mut Line) -> Result<()> {
     let conditional = line.get_conditional()?;
 
     let body = smsh.get_block(line.source(), line.indentation() + 1)?;
 
-    if let Some(res) = smsh.evaluate_conditional(&conditional)? {
-        if res {
-            smsh.push_block(body.clone());
-            smsh.push_line(line.clone());
-            smsh.push_block(body);
-        }
+    let limit = smsh
+        .get_user_variable("while_limit")
+        .and_then(|x| x.parse().ok())
+        .filter(|x| *x > 0);
+
+    smsh.push_source(
+        While::new(conditional, body, limit, line.identifier().clone()).build_source(),
+    );
+
+    smsh.set_rv(0);
+    Ok(())
+}
+
+pub fn r#break(smsh: &mut Shell, line: &mut Line) -> Result<()> {
+    interrupt(smsh, line, Interrupt::Break)
+}
+
+pub fn r#continue(smsh: &mut Shell, line: &mut Line) -> Result<()> {
+    interrupt(smsh, line, Interrupt::Continue)
+}
+
+fn interrupt(smsh: &mut Shell, line: &mut Line, interrupt: Interrupt) -> Result<()> {
+    let argv = line.argv();
+
+    if argv.len() != 1 {
+        smsh.set_rv(2);
+        return Err(anyhow!("{}: Improper invocation of `{}`", argv[0], argv[0]));
+    }
+
+    if !smsh.interrupt_loop(interrupt) {
+        smsh.set_rv(1);
+        return Err(anyhow!("{}: Not in a loop", argv[0]));
     }
+
+    smsh.set_rv(0);
     Ok(())
 }
 
 pub fn r#let(smsh: &mut Shell, line: &mut Line) -> Result<()> {
-    let argv = line.argv();
+    let mut argv = line.argv();
+
+    let global = argv.get(1) == Some(&"--global");
+    if global {
+        argv.remove(1);
+    }
 
     if argv.len() < 4 || argv[2] != "=" {
         smsh.set_rv(1);
@@ -160,6 +239,14 @@ pub fn r#let(smsh: &mut Shell, line: &mut Line) -> Result<()> {
     }
 
     let key = argv[1].to_string();
+
+    if is_special_variable(&key) {
+        smsh.set_rv(1);
+        return Err(anyhow!(
+            "let: `{}` is a special variable, and cannot be assigned to",
+            key
+        ));
+    }
     let mut value = String::new();
 
     for word in &argv[3..] {
@@ -169,7 +256,64 @@ pub fn r#let(smsh: &mut Shell, line: &mut Line) -> Result<()> {
 
     value.pop();
 
-    smsh.insert_user_variable(key, value);
+    smsh.insert_user_variable(key, value, global);
 
+    smsh.set_rv(0);
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sources::SourceKind;
+
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, ForkResult};
+
+    // Runs `lines` in a shell of its own, within a child, yielding its
+    // return value.  The subshells evaluating conditionals exit here, too.
+    fn run_lines(lines: &[&str]) -> i32 {
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Parent { child, .. } => match waitpid(child, None).unwrap() {
+                WaitStatus::Exited(_, rv) => rv,
+                _ => -1,
+            },
+            ForkResult::Child => {
+                let mut smsh = Shell::new(false);
+                let lines = lines
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| Line::new(x.to_string(), i + 1, SourceKind::Subshell).unwrap())
+                    .collect();
+
+                smsh.push_block(lines);
+                let _ = smsh.run();
+
+                let rv = smsh.rv();
+                smsh.exit_child(rv)
+            }
+        }
+    }
+
+    #[test]
+    fn if_1() {
+        // The line after the block runs; it is not lost in looking for `else`
+        assert_eq!(1, run_lines(&["if false", "    true", "false"]));
+        assert_eq!(0, run_lines(&["if true", "    false", "true"]));
+    }
+
+    #[test]
+    fn fn_1() {
+        let mut smsh = Shell::new(false);
+        let body = Line::new("    echo hi".to_string(), 2, SourceKind::Subshell).unwrap();
+        smsh.push_block(vec![body]);
+
+        let mut line = Line::new("fn greet:".to_string(), 1, SourceKind::Subshell).unwrap();
+        line.expand(&mut smsh).unwrap();
+        line.select().unwrap();
+        r#fn(&mut smsh, &mut line).unwrap();
+
+        assert!(smsh.get_user_function("greet").is_some());
+        assert!(smsh.get_user_function("greet:").is_none());
+    }
+}
@@ -0,0 +1,145 @@
+// A Diagnostic points out the part of a line at fault, in the manner of
+// rustc.  Rendered after the `smsh: ` prefix with which errors are reported:
+//
+//      smsh: echo {PATH
+//                 ^ Unmatched expansion brace
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use std::error::Error;
+use std::fmt;
+
+const PREFIX_WIDTH: usize = "smsh: ".len();
+const TAB_WIDTH: usize = 4;
+
+// Byte offsets into a line's raw text, on grapheme boundaries
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Diagnostic {
+    text: String, // The line at fault
+    span: Span,
+    label: String,
+    notes: Vec<String>, // e.g., "help: ..."
+}
+
+impl Diagnostic {
+    pub fn new(text: &str, span: Span, label: &str) -> Self {
+        Diagnostic {
+            text: text.to_string(),
+            span,
+            label: label.to_string(),
+            notes: vec![],
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    // Only the physical line holding the start of the span is shown
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.span.start.min(self.text.len());
+        let end = self.span.end.clamp(start, self.text.len());
+
+        let line_start = self.text[..start].rfind('\n').map(|x| x + 1).unwrap_or(0);
+        let line_end = self.text[start..]
+            .find('\n')
+            .map(|x| x + start)
+            .unwrap_or(self.text.len());
+
+        if line_start > 0 {
+            write!(f, "{}", " ".repeat(PREFIX_WIDTH))?;
+        }
+
+        writeln!(
+            f,
+            "{}",
+            self.text[line_start..line_end].replace('\t', "    ")
+        )?;
+
+        let offset = PREFIX_WIDTH + display_width(&self.text[line_start..start]);
+        let carets = display_width(&self.text[start..end.min(line_end)]).max(1);
+
+        write!(
+            f,
+            "{}{} {}",
+            " ".repeat(offset),
+            "^".repeat(carets),
+            self.label
+        )?;
+
+        for note in &self.notes {
+            write!(f, "\n{}= {}", " ".repeat(PREFIX_WIDTH), note)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for Diagnostic {}
+
+// Number of terminal columns taken by `text`
+fn display_width(text: &str) -> usize {
+    text.graphemes(true)
+        .map(|x| if x == "\t" { TAB_WIDTH } else { x.width() })
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_diagnostic_1() {
+        let diagnostic =
+            Diagnostic::new("echo {PATH", Span::new(5, 6), "Unmatched expansion brace");
+        let expected = "echo {PATH\n           ^ Unmatched expansion brace";
+        assert_eq!(expected, diagnostic.to_string());
+    }
+
+    #[test]
+    fn render_diagnostic_2() {
+        let diagnostic = Diagnostic::new("celar", Span::new(0, 5), "Command not found")
+            .with_note("help: Did you mean `clear`?".to_string());
+        let expected = "celar\n      ^^^^^ Command not found\n      = help: Did you mean `clear`?";
+        assert_eq!(expected, diagnostic.to_string());
+    }
+
+    #[test]
+    fn render_diagnostic_3() {
+        // Each CJK character takes two columns
+        let text = "echo 日本 {x";
+        let start = text.find('{').unwrap();
+        let diagnostic = Diagnostic::new(text, Span::new(start, start + 1), "Unmatched");
+        let expected = "echo 日本 {x\n                ^ Unmatched";
+        assert_eq!(expected, diagnostic.to_string());
+    }
+
+    #[test]
+    fn render_diagnostic_4() {
+        let text = "echo 'one\ntwo' {x";
+        let start = text.find('{').unwrap();
+        let diagnostic = Diagnostic::new(text, Span::new(start, start + 1), "Unmatched");
+        let expected = "      two' {x\n           ^ Unmatched";
+        assert_eq!(expected, diagnostic.to_string());
+    }
+}
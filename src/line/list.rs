@@ -0,0 +1,209 @@
+// A Normal line is a list of pipelines joined by `and` and `or`, each of
+// which may be preceded by `not`.  The list is evaluated left to right:
+// `and` skips its pipeline if the return value so far is nonzero, and `or`
+// skips its pipeline if it is zero.
+
+use anyhow::{anyhow, Result};
+
+use super::pipeline::Pipeline;
+use super::token::Token;
+use super::Line;
+use crate::Shell;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Connector {
+    First,
+    And,
+    Or,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+struct ListElement {
+    connector: Connector, // Joins the element to its predecessor
+    negated: bool,        // Preceded by an odd number of `not`s
+    start: usize,         // Token range of the pipeline within the line
+    end: usize,
+}
+
+// An error in any but the last pipeline is reported, and
+// counts as failure
+pub fn execute_list(line: &mut Line, smsh: &mut Shell) -> Result<()> {
+    let elements = get_list_elements(line.tokens())?;
+    let last = elements.len().saturating_sub(1);
+
+    for (i, elem) in elements.into_iter().enumerate() {
+        let skip = match elem.connector {
+            Connector::First => false,
+            Connector::And => smsh.rv() != 0,
+            Connector::Or => smsh.rv() == 0,
+        };
+
+        if skip {
+            continue;
+        }
+
+        let res = line
+            .expand_tokens(elem.start..elem.end, smsh)
+            .and_then(|_| Pipeline::new(line, &line.tokens()[elem.start..elem.end], smsh))
+            .and_then(|mut x| x.execute(smsh));
+
+        if let Err(e) = res {
+            if i == last {
+                return Err(e);
+            }
+
+            eprintln!("smsh: {}", e);
+            if smsh.rv() == 0 {
+                smsh.set_rv(1);
+            }
+        }
+
+        if elem.negated {
+            let rv = if smsh.rv() == 0 { 1 } else { 0 };
+            smsh.set_rv(rv);
+        }
+    }
+
+    Ok(())
+}
+
+fn get_list_elements(tokens: &[Token]) -> Result<Vec<ListElement>> {
+    let mut elements = Vec::<ListElement>::new();
+    let mut connector = Connector::First;
+    let mut negated = false;
+    let mut start = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let next = if token.is_and_operator() {
+            Connector::And
+        } else if token.is_or_operator() {
+            Connector::Or
+        } else {
+            if token.is_not_operator() && i == start {
+                negated = !negated;
+                start += 1;
+            }
+            continue;
+        };
+
+        if i == start {
+            return Err(anyhow!("{}: Missing command", token.text()));
+        }
+
+        elements.push(ListElement {
+            connector,
+            negated,
+            start,
+            end: i,
+        });
+
+        connector = next;
+        negated = false;
+        start = i + 1;
+    }
+
+    if start == tokens.len() {
+        return match connector {
+            Connector::First if !negated => Ok(elements),
+            Connector::First => Err(anyhow!("not: Missing command")),
+            Connector::And => Err(anyhow!("and: Missing command")),
+            Connector::Or => Err(anyhow!("or: Missing command")),
+        };
+    }
+
+    elements.push(ListElement {
+        connector,
+        negated,
+        start,
+        end: tokens.len(),
+    });
+
+    Ok(elements)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::line::token::get_tokens;
+    use crate::sources::SourceKind;
+
+    fn execute_line(smsh: &mut Shell, text: &str) -> Result<()> {
+        let mut line = Line::new(text.to_string(), 1, SourceKind::Subshell)?;
+        line.execute(smsh)
+    }
+
+    fn element(connector: Connector, negated: bool, start: usize, end: usize) -> ListElement {
+        ListElement {
+            connector,
+            negated,
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn get_list_elements_1() {
+        let tokens = get_tokens("test -f x and test -r x").unwrap();
+        let elements = vec![
+            element(Connector::First, false, 0, 3),
+            element(Connector::And, false, 4, 7),
+        ];
+        assert_eq!(elements, get_list_elements(&tokens).unwrap());
+    }
+
+    #[test]
+    fn get_list_elements_2() {
+        let tokens = get_tokens("not false or not not true").unwrap();
+        let elements = vec![
+            element(Connector::First, true, 1, 2),
+            element(Connector::Or, false, 5, 6),
+        ];
+        assert_eq!(elements, get_list_elements(&tokens).unwrap());
+    }
+
+    #[test]
+    fn get_list_elements_3() {
+        let tokens = get_tokens("echo 'and' \"or\" not").unwrap();
+        let elements = vec![element(Connector::First, false, 0, 4)];
+        assert_eq!(elements, get_list_elements(&tokens).unwrap());
+    }
+
+    #[test]
+    fn get_list_elements_4() {
+        for line in ["and true", "true or", "true and or false", "not"] {
+            let tokens = get_tokens(line).unwrap();
+            assert!(get_list_elements(&tokens).is_err());
+        }
+    }
+
+    #[test]
+    fn execute_list_1() {
+        let mut smsh = Shell::new(false);
+
+        // An error in an earlier pipeline counts as failure
+        execute_line(&mut smsh, "true > or true").unwrap();
+        assert_eq!(0, smsh.rv());
+        execute_line(&mut smsh, "true > and true").unwrap();
+        assert_eq!(1, smsh.rv());
+
+        // An error in the last is the line's
+        assert!(execute_line(&mut smsh, "true and true >").is_err());
+    }
+
+    #[test]
+    fn execute_list_2() {
+        let mut smsh = Shell::new(false);
+        let probe = std::env::temp_dir().join(format!("smsh_list_probe_{}", std::process::id()));
+        let probe = probe.to_string_lossy();
+
+        // A skipped pipeline is not expanded
+        execute_line(&mut smsh, &format!("false and echo !{{touch {}}}", probe)).unwrap();
+        assert!(!std::path::Path::new(probe.as_ref()).exists());
+        execute_line(&mut smsh, &format!("true or echo !{{touch {}}}", probe)).unwrap();
+        assert!(!std::path::Path::new(probe.as_ref()).exists());
+
+        // A pipeline is expanded only once those before it have run
+        execute_line(&mut smsh, "false or test {rv} = 1").unwrap();
+        assert_eq!(0, smsh.rv());
+    }
+}
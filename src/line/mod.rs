@@ -1,16 +1,23 @@
 use std::fmt;
+use std::ops::Range;
 
 use anyhow::{anyhow, Result};
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::constructs::{r#fn, r#for, r#if, r#let, r#while};
+use crate::constructs::{r#break, r#continue, r#fn, r#for, r#if, r#let, r#while};
 use crate::shell::Shell;
 use crate::sources::SourceKind;
 
+mod diagnostic;
+pub use diagnostic::Diagnostic;
 mod token;
-use token::{get_tokens, Token};
+pub use token::format_local_time;
+pub use token::{get_tokens, Expansion, Quote, Token};
+mod list;
+use list::execute_list;
 mod pipeline;
-use pipeline::Pipeline;
+mod redirection;
+pub use redirection::is_redirection_operator;
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Construct {
@@ -21,6 +28,8 @@ pub enum Construct {
     For,
     While,
     Let,
+    Break,
+    Continue,
 }
 
 // Reflects the kind of command held by Line
@@ -52,6 +61,9 @@ pub struct Line {
     tokens: Vec<Token>,
     line_type: LineType,
     indentation: usize,
+    here_doc: Option<String>, // Dedented body of a here-document, once gathered
+    error: Option<String>,    // Reported upon expansion, so that the line may
+                              // still serve as the body of a here-document
 }
 
 impl Line {
@@ -62,9 +74,12 @@ impl Line {
         };
 
         // Break logical line into parts according to quoting rules
-        let tokens = get_tokens(raw_text.as_str())?;
+        let (tokens, error) = match get_tokens(raw_text.as_str()) {
+            Ok(tokens) => (tokens, None),
+            Err(e) => (Vec::<Token>::new(), Some(e.to_string())),
+        };
 
-        let line_type = if tokens.len() > 0 {
+        let line_type = if !tokens.is_empty() {
             determine_line_type(tokens[0].text())
         } else {
             LineType::Empty
@@ -78,6 +93,8 @@ impl Line {
             tokens,
             line_type,
             indentation,
+            here_doc: None,
+            error,
         })
     }
 
@@ -96,24 +113,36 @@ impl Line {
         strs
     }
 
+    // Expands and executes the line.  The pipelines of a list are each
+    // expanded only once reached, so that those skipped by `and` and `or`
+    // have no effect, and see the return value of those run before them.
     pub fn execute(&mut self, smsh: &mut Shell) -> Result<()> {
-        match &self.line_type {
+        match self.line_type.clone() {
             LineType::Normal => {
-                let mut pipeline = Pipeline::new(self, smsh)?;
-                pipeline.execute(smsh)
+                self.gather_here_doc(smsh)?;
+                execute_list(self, smsh)
+            }
+            LineType::Empty => self.gather_here_doc(smsh),
+            LineType::ShellConstruct(c) => {
+                self.expand(smsh)?;
+                self.select()?;
+
+                match c {
+                    Construct::If | Construct::Elif | Construct::Else => r#if(smsh, self),
+                    Construct::FunctionDefinition => r#fn(smsh, self),
+                    Construct::For => r#for(smsh, self),
+                    Construct::Let => r#let(smsh, self),
+                    Construct::While => r#while(smsh, self),
+                    Construct::Break => r#break(smsh, self),
+                    Construct::Continue => r#continue(smsh, self),
+                }
             }
-            LineType::Empty => Ok(()),
-            LineType::ShellConstruct(c) => match c {
-                Construct::If | Construct::Elif | Construct::Else => r#if(smsh, self),
-                Construct::FunctionDefinition => r#fn(smsh, self),
-                Construct::For => r#for(smsh, self),
-                Construct::Let => r#let(smsh, self),
-                Construct::While => r#while(smsh, self),
-            },
         }
     }
 
     pub fn expand(&mut self, smsh: &mut Shell) -> Result<()> {
+        self.gather_here_doc(smsh)?;
+
         for token in &mut self.tokens {
             token.expand(smsh)?;
         }
@@ -121,21 +150,50 @@ impl Line {
         Ok(())
     }
 
-    pub fn get_conditional(&self) -> Result<String> {
-        let mut conditional = String::new();
+    // Expands and selects the tokens in `range` alone
+    pub fn expand_tokens(&mut self, range: Range<usize>, smsh: &mut Shell) -> Result<()> {
+        for token in &mut self.tokens[range] {
+            token.expand(smsh)?;
+            token.select()?;
+        }
 
-        if self.tokens.len() < 2 {
-            return Err(anyhow!("No conditional present"));
+        Ok(())
+    }
+
+    // The body of a here-document is gathered before anything else
+    // can fail, so that it is never mistaken for commands
+    fn gather_here_doc(&mut self, smsh: &mut Shell) -> Result<()> {
+        if let Some(e) = &self.error {
+            return Err(anyhow!("{}", e));
         }
 
-        for s in &self.tokens[1..] {
-            conditional.push_str(s.text());
-            conditional.push(' ');
+        if self.here_doc.is_none() && self.tokens.iter().any(|x| x.is_here_doc_operator()) {
+            let body = smsh.get_here_doc_body(self.source(), self.indentation + 1)?;
+            let body: Vec<&str> = body.iter().map(|x| x.raw_text()).collect();
+            self.here_doc = Some(dedent(&body));
         }
 
-        conditional.pop(); // Remove trailing whitespace
+        Ok(())
+    }
 
-        Ok(conditional)
+    // Yields the raw text following the keyword, so that quoting
+    // survives into the line that evaluates the conditional
+    pub fn get_conditional(&self) -> Result<String> {
+        let text = self.raw_text.trim();
+        let conditional = match text.find(char::is_whitespace) {
+            Some(i) => text[i..].trim_start(),
+            None => "",
+        };
+
+        if conditional.is_empty() {
+            return Err(anyhow!("No conditional present"));
+        }
+
+        Ok(conditional.to_string())
+    }
+
+    pub fn here_doc(&self) -> Option<&str> {
+        self.here_doc.as_deref()
     }
 
     pub fn identifier(&self) -> &LineID {
@@ -154,6 +212,14 @@ impl Line {
         self.line_type == LineType::ShellConstruct(Construct::Else)
     }
 
+    // Constructs that gather a block cannot serve as conditionals
+    pub fn is_conditional(&self) -> bool {
+        matches!(
+            self.line_type,
+            LineType::Normal | LineType::ShellConstruct(Construct::Let)
+        )
+    }
+
     pub fn raw_text(&self) -> &str {
         &self.raw_text
     }
@@ -175,6 +241,16 @@ impl Line {
     }
 }
 
+// Expands `text` as a line of its own, yielding its words joined by spaces
+pub fn expand_text(text: &str, smsh: &mut Shell) -> Result<String> {
+    let mut line = Line::new(text.to_string(), 0, SourceKind::Subshell)?;
+
+    line.expand(smsh)?;
+    line.select()?;
+
+    Ok(line.argv().join(" "))
+}
+
 impl fmt::Display for Line {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self.line_id.source_kind {
@@ -202,13 +278,27 @@ impl fmt::Display for Line {
                     s, self.line_id.line_num, self.raw_text
                 )
             }
+            SourceKind::Stdin => {
+                write!(
+                    f,
+                    "\tStdin line {}: {}",
+                    self.line_id.line_num, self.raw_text
+                )
+            }
+            SourceKind::Command => {
+                write!(
+                    f,
+                    "\tCommand line {}: {}",
+                    self.line_id.line_num, self.raw_text
+                )
+            }
         }
     }
 }
 
 // TODO: Remove! This functionality is not even close to being
 // complete; should probably be elsewhere...
-fn determine_line_type(first_token: &str) -> LineType {
+pub fn determine_line_type(first_token: &str) -> LineType {
     match first_token {
         "if" => LineType::ShellConstruct(Construct::If),
         "elif" => LineType::ShellConstruct(Construct::Elif),
@@ -217,11 +307,13 @@ fn determine_line_type(first_token: &str) -> LineType {
         "for" => LineType::ShellConstruct(Construct::For),
         "while" => LineType::ShellConstruct(Construct::While),
         "let" => LineType::ShellConstruct(Construct::Let),
+        "break" => LineType::ShellConstruct(Construct::Break),
+        "continue" => LineType::ShellConstruct(Construct::Continue),
         _ => LineType::Normal,
     }
 }
 
-fn determine_indentation(line: &str) -> usize {
+pub fn determine_indentation(line: &str) -> usize {
     let mut spaces: usize = 0;
     let mut indentation = 0;
 
@@ -248,115 +340,50 @@ fn determine_indentation(line: &str) -> usize {
     indentation
 }
 
-// Breaks `rawline` into parts according to quoting rules, yielding parts.
-// Quotes and escapes are preserved; unquoted whitespace is removed
-// Selection remains appended to part.
-pub fn get_parts(rawline: &str) -> Result<Vec<&str>> {
-    #[derive(PartialEq, Eq, Clone, Copy)]
-    enum State {
-        SingleQuoted,
-        DoubleQuoted,
-        Escaped,
-        Unquoted,
-        Expansion,
-    }
-
-    let mut i: usize = 0;
-    let mut last: usize = 0;
-    let mut parts = Vec::<&str>::new();
-
-    let mut state = State::Unquoted;
-    let mut escaped_state: Option<State> = None;
+// Removes the leading whitespace of the first line that is not blank
+// from it and all other lines.  Lines indented less than it lose only
+// their leading whitespace.
+fn dedent(lines: &[&str]) -> String {
+    let prefix = match lines.iter().find(|x| !x.trim().is_empty()) {
+        Some(first) => {
+            let len = first.len() - first.trim_start_matches([' ', '\t']).len();
+            &first[..len]
+        }
+        None => "",
+    };
 
-    for (j, grapheme) in rawline.grapheme_indices(true) {
-        match state {
-            State::Unquoted => match grapheme {
-                " " | "\t" => {
-                    if j > i {
-                        let s = &rawline[i..j];
-                        parts.push(s);
-                    }
+    let mut text = String::new();
 
-                    i = j + 1;
-                }
-                "\'" => {
-                    state = State::SingleQuoted;
-                }
-                "\"" => {
-                    state = State::DoubleQuoted;
-                }
-                "\\" => {
-                    escaped_state = Some(State::Unquoted);
-                    state = State::Escaped;
-                }
-                "{" => {
-                    state = State::Expansion;
-                }
-                _ => {}
-            },
-            State::SingleQuoted => {
-                if grapheme == "\'" {
-                    state = State::Unquoted;
-                }
-            }
-            State::DoubleQuoted => {
-                if grapheme == "\"" {
-                    state = State::Unquoted;
-                } else if grapheme == "\\" {
-                    escaped_state = Some(State::DoubleQuoted);
-                    state = State::Escaped;
-                }
-            }
-            State::Escaped => {
-                state = escaped_state.unwrap();
-            }
-            State::Expansion => {
-                if grapheme == "\\" {
-                    escaped_state = Some(State::Expansion);
-                    state = State::Escaped;
-                } else if grapheme == "}" {
-                    state = State::Unquoted;
-                }
-            }
+    for line in lines {
+        match line.strip_prefix(prefix) {
+            Some(stripped) => text.push_str(stripped),
+            None => text.push_str(line.trim_start_matches([' ', '\t'])),
         }
-        last = j;
+        text.push('\n');
     }
 
-    if last > i {
-        let s = &rawline[i..];
-        parts.push(s);
-    }
-
-    match state {
-        State::SingleQuoted => Err(anyhow!("Unmatched single quote")),
-        State::DoubleQuoted => Err(anyhow!("Unmatched double quote")),
-        State::Escaped => Err(anyhow!("Line terminates in escape character")),
-        State::Expansion => Err(anyhow!("Unmatched expansion brace")),
-        State::Unquoted => Ok(parts),
-    }
+    text
 }
 
+#[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
-    fn get_parts_1() {
-        let line = "";
-        assert_eq!(Vec::<&str>::new(), get_parts(line).unwrap());
+    fn dedent_1() {
+        let lines = vec!["    import sys", "    if True:", "        print(1)"];
+        assert_eq!("import sys\nif True:\n    print(1)\n", dedent(&lines));
     }
 
     #[test]
-    fn get_parts_2() {
-        let line = "echo one two three";
-        let v = vec!["echo", "one", "two", "three"];
-        assert_eq!(v, get_parts(line).unwrap());
+    fn dedent_2() {
+        let lines = vec!["\t\tSELECT *", "\tFROM t;"];
+        assert_eq!("SELECT *\nFROM t;\n", dedent(&lines));
     }
 
     #[test]
-    fn get_parts_3() {
-        let line = "echo   one two three  ";
-        let v = vec!["echo", "one", "two", "three"];
-        assert_eq!(v, get_parts(line).unwrap());
+    fn dedent_3() {
+        let lines = vec!["", "    a", "", "    b"];
+        assert_eq!("\na\n\nb\n", dedent(&lines));
     }
-
 }
@@ -1,136 +1,264 @@
 use anyhow::{anyhow, Result};
-use nix::sys::wait::{waitpid, WaitStatus};
+use nix::errno::Errno;
 use nix::unistd::{close, dup, dup2, fork, pipe, ForkResult};
 
-use std::os::unix::io::RawFd;
-
+use super::diagnostic::{Diagnostic, Span};
+use super::redirection::{get_redirections, redirect, Redirection, SavedFds};
+use super::token::Token;
 use super::Line;
+use crate::shell::jobs::{enter_process_group, Job, JobState};
 use crate::shell::modules::Builtin;
 use crate::sources::user_function::UserFunction;
+use crate::sources::SourceKind;
 use crate::Shell;
 
+use std::path::PathBuf;
+
 enum CommandKind {
     UserFunction(UserFunction),
     Builtin(Builtin),
-    ExternalCommand(String),
+    ExternalCommand(PathBuf), // As resolved on PATH
 }
 
 pub struct Pipeline {
     elements: Vec<PipeElement>,
+    background: bool, // Set by a trailing `&`
+    text: String,     // As listed by `jobs`
 }
 
 impl Pipeline {
-    pub fn new(line: &mut Line, smsh: &mut Shell) -> Result<Self> {
+    // `tokens` is the part of `line` that makes up the pipeline
+    pub fn new(line: &Line, tokens: &[Token], smsh: &mut Shell) -> Result<Self> {
         let mut args = Vec::<String>::new();
+        let mut redirections = Vec::<Redirection>::new();
         let mut elements = Vec::<PipeElement>::new();
+        let mut input = Vec::<Redirection>::new(); // Fed to first element
+        let mut span = Span::default(); // Of the current element's command
 
-        for token in line.tokens() {
+        let (tokens, background) = match tokens.split_last() {
+            Some((last, rest)) if last.is_background_operator() => (rest, true),
+            _ => (tokens, false),
+        };
+
+        // Only the pipeline's own part of the line is listed
+        let text = match (tokens.first(), tokens.last()) {
+            (Some(first), Some(last)) => line
+                .raw_text()
+                .get(first.span().start..last.span().end)
+                .unwrap_or_default(),
+            _ => "",
+        };
+
+        let mut tokens = tokens.iter();
+
+        while let Some(token) = tokens.next() {
             if token.is_pipe_operator() {
-                let elem = PipeElement::new(args, smsh)?;
+                let elem = PipeElement::new(args, redirections, (line, span), smsh)?;
                 args = Vec::<String>::new();
+                redirections = Vec::<Redirection>::new();
                 elements.push(elem);
+            } else if token.is_here_doc_operator() {
+                let text = line.here_doc().unwrap_or_default().to_string();
+                input.push(Redirection::Text { fd: 0, text });
+            } else if let Some(redirs) = get_redirections(token, &mut tokens)? {
+                for redir in redirs {
+                    if redir.is_text() {
+                        input.push(redir);
+                    } else {
+                        redirections.push(redir);
+                    }
+                }
             } else {
                 for arg in token.selected_text() {
                     if !arg.is_empty() {
+                        if args.is_empty() {
+                            span = token.span();
+                        }
                         args.push(arg.to_string());
-                    } else {
                     }
                 }
             }
         }
 
-        if args.len() > 0 {
-            let elem = PipeElement::new(args, smsh)?;
+        if !args.is_empty() {
+            let elem = PipeElement::new(args, redirections, (line, span), smsh)?;
             elements.push(elem);
+        } else if !redirections.is_empty() || !input.is_empty() {
+            return Err(anyhow!("Redirection without command"));
+        }
+
+        // Text goes ahead of the first element's own redirections,
+        // so that an explicit `<` takes precedence
+        if let Some(first) = elements.first_mut() {
+            input.append(&mut first.redirections);
+            first.redirections = input;
         }
 
-        Ok(Pipeline { elements })
+        Ok(Pipeline {
+            elements,
+            background,
+            text: text.to_string(),
+        })
     }
 
     pub fn execute(&mut self, smsh: &mut Shell) -> Result<()> {
-        if self.elements.len() == 0 {
-            return Ok(());
-        }
+        let mut last_elem = match self.elements.pop() {
+            Some(elem) => elem,
+            None => return Ok(()),
+        };
 
-        let last = self.elements.len() - 1;
+        // Unless the pipeline runs in the background, a builtin or user
+        // function in last position runs within the shell itself
+        let in_shell = !self.background && !last_elem.is_external_command();
+        let job_control = smsh.jobs().has_job_control() && !in_shell;
+        let foreground = !self.background;
 
-        let piped = if self.elements.len() > 1 {
-            let stdin = dup(0)?;
-            Some(stdin)
+        let stdin = if !self.elements.is_empty() {
+            Some(dup(0)?)
         } else {
             None
         };
 
-        for elem in &mut self.elements[..last] {
+        let mut job = Job::new(self.text.clone());
+
+        for elem in &mut self.elements {
             let (rd, wr) = pipe()?;
 
             match unsafe { fork()? } {
-                ForkResult::Parent { child: _, .. } => {
+                ForkResult::Parent { child, .. } => {
                     close(wr)?;
-                    close(0 as RawFd)?;
                     dup2(rd, 0)?;
+                    close(rd)?;
+                    job.push(child, job_control);
                 }
 
                 ForkResult::Child => {
-                    smsh.clear_sources();
+                    if job_control {
+                        enter_process_group(job.pgid(), foreground);
+                    }
+
                     close(rd)?;
-                    close(1 as RawFd)?;
                     dup2(wr, 1)?;
                     close(wr)?;
 
-                    elem.execute(smsh)?;
+                    elem.execute_in_child(smsh);
                 }
             }
         }
 
-        let mut last_elem = self.elements.pop().unwrap();
-
-        if last_elem.is_external_command() {
+        let res = if in_shell {
+            last_elem.execute(smsh)
+        } else {
             match unsafe { fork()? } {
-                ForkResult::Parent { child: pid, .. } => match waitpid(pid, None)? {
-                    WaitStatus::Exited(_pid, exit_status) => {
-                        smsh.set_rv(exit_status);
-                    }
-                    _ => {}
-                },
+                ForkResult::Parent { child, .. } => {
+                    job.push(child, job_control);
+                    Ok(())
+                }
                 ForkResult::Child => {
-                    smsh.clear_sources();
-                    last_elem.execute(smsh)?;
+                    if job_control {
+                        enter_process_group(job.pgid(), foreground);
+                    }
+
+                    last_elem.execute_in_child(smsh)
                 }
             }
-        } else {
-            last_elem.execute(smsh)?;
-        }
+        };
 
-        if let Some(stdin) = piped {
-            close(0 as RawFd)?;
+        if let Some(stdin) = stdin {
             dup2(stdin, 0)?;
+            close(stdin)?;
+        }
+
+        if job.is_empty() {
+            return res;
         }
 
-        Ok(())
+        if in_shell {
+            // The return value belongs to the last element, which has
+            // already set it
+            job.wait()?;
+
+            if job.state() == JobState::Stopped {
+                smsh.jobs().insert(job);
+            }
+        } else if self.background {
+            let pid = job.last_pid().unwrap();
+            smsh.set_last_background_pid(pid);
+            let id = smsh.jobs().insert(job);
+
+            if smsh.is_interactive() {
+                eprintln!("[{}] {}", id, pid);
+            }
+
+            smsh.set_rv(0);
+        } else {
+            let rv = smsh.jobs().foreground(job)?;
+            smsh.set_rv(rv);
+        }
+
+        res
     }
 }
 
 pub struct PipeElement {
     argv: Vec<String>,
+    redirections: Vec<Redirection>,
     cmd_kind: CommandKind,
+    line_text: String, // Of the line holding the element, for diagnostics
+    span: Span,        // Of the command within `line_text`
+}
+
+// Within a script module's function, the module's own functions are
+// called by their names within the module, and come before any other
+// function of the same name
+fn get_user_function(name: &str, source: &SourceKind, smsh: &Shell) -> Option<UserFunction> {
+    let module = match source {
+        SourceKind::UserFunction(caller) if !name.contains("::") => {
+            caller.split_once("::").map(|x| x.0)
+        }
+        _ => None,
+    };
+
+    module
+        .and_then(|x| smsh.get_user_function(&format!("{}::{}", x, name)))
+        .or_else(|| smsh.get_user_function(name))
 }
 
 impl PipeElement {
-    pub fn new(argv: Vec<String>, smsh: &mut Shell) -> Result<Self> {
-        if argv.len() == 0 {
+    pub fn new(
+        argv: Vec<String>,
+        redirections: Vec<Redirection>,
+        (line, span): (&Line, Span),
+        smsh: &mut Shell,
+    ) -> Result<Self> {
+        if argv.is_empty() {
             return Err(anyhow!("Cannot create empty pipeline element"));
         }
 
-        let cmd_kind = if let Some(f) = smsh.get_user_function(&argv[0]) {
+        let cmd_kind = if let Some(f) = get_user_function(&argv[0], line.source(), smsh) {
             CommandKind::UserFunction(f)
         } else if let Some(f) = smsh.get_builtin(&argv[0]) {
             CommandKind::Builtin(*f)
+        } else if let Some(path) = smsh.resolve_command(&argv[0]) {
+            CommandKind::ExternalCommand(path)
         } else {
-            CommandKind::ExternalCommand(argv[0].to_string())
+            smsh.set_rv(127);
+
+            let mut diagnostic = Diagnostic::new(line.raw_text(), span, "Command not found");
+            if let Some(name) = smsh.suggest_command(&argv[0]) {
+                diagnostic = diagnostic.with_note(format!("help: Did you mean `{}`?", name));
+            }
+
+            return Err(diagnostic.into());
         };
 
-        Ok(PipeElement { argv, cmd_kind })
+        Ok(PipeElement {
+            argv,
+            redirections,
+            cmd_kind,
+            line_text: line.raw_text().to_string(),
+            span,
+        })
     }
 
     pub fn argv(&self) -> Vec<&str> {
@@ -146,24 +274,86 @@ impl PipeElement {
     }
 
     pub fn is_external_command(&self) -> bool {
-        match &self.cmd_kind {
-            CommandKind::ExternalCommand(_) => true,
-            _ => false,
-        }
+        matches!(&self.cmd_kind, CommandKind::ExternalCommand(_))
     }
 
-    // Executes self in current shell context (that is, 'dumbly')
+    // Executes self in current shell context, redirecting the shell's own
+    // file descriptors for the duration of the command
     pub fn execute(&mut self, smsh: &mut Shell) -> Result<()> {
-        match &self.cmd_kind {
+        let saved_fds = SavedFds::redirect(&self.redirections)?;
+
+        let res = match &self.cmd_kind {
             CommandKind::UserFunction(f) => {
-                smsh.push_source(f.clone().build_source());
-                Ok(())
+                smsh.run_source(f.clone().build_source(self.argv.clone()))
             }
             CommandKind::Builtin(b) => b(smsh, self.argv()),
-            CommandKind::ExternalCommand(cmd) => {
-                let _ = smsh.execute_external_command(self.argv());
-                Err(anyhow!("{}: Command not found", cmd))
+            CommandKind::ExternalCommand(_) => Err(anyhow!(
+                "{}: External commands cannot be executed in the shell process",
+                self.argv[0]
+            )),
+        };
+
+        saved_fds.restore()?;
+
+        res
+    }
+
+    // Executes self within a forked child; never returns
+    pub fn execute_in_child(&mut self, smsh: &mut Shell) -> ! {
+        smsh.become_child();
+        smsh.clear_sources();
+
+        if let Err(e) = redirect(&self.redirections) {
+            eprintln!("smsh: {}", e);
+            smsh.exit_child(1);
+        }
+
+        let res = match &self.cmd_kind {
+            CommandKind::UserFunction(f) => {
+                smsh.run_source(f.clone().build_source(self.argv.clone()))
+            }
+            CommandKind::Builtin(b) => b(smsh, self.argv()),
+            CommandKind::ExternalCommand(path) => {
+                let (rv, label) = match smsh.execute_external_command(path, self.argv()) {
+                    Errno::ENOENT => (127, "Command not found"),
+                    e => (126, e.desc()),
+                };
+                smsh.set_rv(rv);
+                Err(Diagnostic::new(&self.line_text, self.span, label).into())
             }
+        };
+
+        if let Err(e) = res {
+            eprintln!("smsh: {}", e);
         }
+
+        let rv = smsh.rv();
+        smsh.exit_child(rv)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pipeline_1() {
+        let mut smsh = Shell::new(false);
+        let mut line = Line::new(
+            "true | true & and true".to_string(),
+            1,
+            SourceKind::Subshell,
+        )
+        .unwrap();
+        line.expand_tokens(0..line.tokens().len(), &mut smsh)
+            .unwrap();
+
+        // Each job is listed as its own pipeline
+        let first = Pipeline::new(&line, &line.tokens()[..4], &mut smsh).unwrap();
+        assert_eq!("true | true", first.text);
+        assert!(first.background);
+
+        let second = Pipeline::new(&line, &line.tokens()[5..], &mut smsh).unwrap();
+        assert_eq!("true", second.text);
     }
 }
@@ -0,0 +1,409 @@
+// Redirections are attached to pipeline elements.  A redirection operator
+// is an unquoted, unexpanded token such as `>`, `>>`, `<`, `2>`, `2>&1` or
+// `&>`; operators that write to or read from a file consume the following
+// token as their target.
+//
+// Here-documents (`<<`, followed by an indented block) and here-strings
+// (`<<< word`) feed text to the stdin of the pipeline's first element.
+
+use anyhow::{anyhow, Result};
+use nix::fcntl::{fcntl, open, FcntlArg, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::{close, dup2};
+
+use std::env;
+use std::fs::{remove_file, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::token::Token;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OpenMode {
+    Read,
+    Truncate,
+    Append,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Redirection {
+    // Opens `path` onto `fd`
+    File {
+        fd: RawFd,
+        path: String,
+        mode: OpenMode,
+    },
+    // Makes `fd` a duplicate of `target`
+    Fd {
+        fd: RawFd,
+        target: RawFd,
+    },
+    // Places `text` at `fd`, as with here-documents and here-strings
+    Text {
+        fd: RawFd,
+        text: String,
+    },
+}
+
+// A redirection operator, prior to being joined with its target
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Operator {
+    ToFile(RawFd, OpenMode), // [n]>, [n]>>, [n]<
+    ToFd(RawFd, RawFd),      // [n]>&m
+    AllToFile(OpenMode),     // &>, &>>
+    HereString,              // <<<
+}
+
+impl Redirection {
+    // The file descriptor this redirection replaces
+    pub fn fd(&self) -> RawFd {
+        match self {
+            Redirection::File { fd, .. } => *fd,
+            Redirection::Fd { fd, .. } => *fd,
+            Redirection::Text { fd, .. } => *fd,
+        }
+    }
+
+    pub fn apply(&self) -> Result<()> {
+        match self {
+            Redirection::File { fd, path, mode } => {
+                let flags = match mode {
+                    OpenMode::Read => OFlag::O_RDONLY,
+                    OpenMode::Truncate => OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC,
+                    OpenMode::Append => OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND,
+                };
+
+                let file = open(path.as_str(), flags, Mode::from_bits_truncate(0o644))
+                    .map_err(|e| anyhow!("{}: {}", path, e.desc()))?;
+
+                if file != *fd {
+                    dup2(file, *fd)?;
+                    close(file)?;
+                }
+            }
+            Redirection::Fd { fd, target } => {
+                dup2(*target, *fd).map_err(|_| anyhow!("{}: Bad file descriptor", target))?;
+            }
+            Redirection::Text { fd, text } => {
+                let file = text_file(text)?;
+
+                if file != *fd {
+                    dup2(file, *fd)?;
+                    close(file)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // True for here-documents and here-strings
+    pub fn is_text(&self) -> bool {
+        matches!(self, Redirection::Text { .. })
+    }
+}
+
+// Text is backed by an unlinked temporary file rather than a pipe,
+// so that writing it can never block on a reader
+fn text_file(text: &str) -> Result<RawFd> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_nanos())
+        .unwrap_or(0);
+    let path = env::temp_dir().join(format!("smsh-{}-{}", std::process::id(), nanos));
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?;
+    remove_file(&path)?;
+
+    file.write_all(text.as_bytes())?;
+    file.seek(SeekFrom::Start(0))?;
+
+    Ok(file.into_raw_fd())
+}
+
+// Applies `redirections` in order.  Used in forked children, where
+// nothing needs to be restored afterwards.
+pub fn redirect(redirections: &[Redirection]) -> Result<()> {
+    for redirection in redirections {
+        redirection.apply()?;
+    }
+
+    Ok(())
+}
+
+// Copies of the file descriptors displaced by a set of redirections.
+// Lets builtins and user functions be redirected within the shell process.
+pub struct SavedFds {
+    saved: Vec<(RawFd, Option<RawFd>)>,
+}
+
+impl SavedFds {
+    pub fn redirect(redirections: &[Redirection]) -> Result<SavedFds> {
+        let mut saved_fds = SavedFds { saved: vec![] };
+
+        for redirection in redirections {
+            let fd = redirection.fd();
+
+            if !saved_fds.saved.iter().any(|(x, _)| *x == fd) {
+                // Keep copies above the range users are likely to redirect,
+                // and out of reach of any children
+                let copy = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(10)).ok();
+                saved_fds.saved.push((fd, copy));
+            }
+
+            if let Err(e) = redirection.apply() {
+                saved_fds.restore()?;
+                return Err(e);
+            }
+        }
+
+        Ok(saved_fds)
+    }
+
+    pub fn restore(self) -> Result<()> {
+        // Anything still buffered belongs to the redirected descriptors
+        io::stdout().flush()?;
+        io::stderr().flush()?;
+
+        for (fd, copy) in self.saved.into_iter().rev() {
+            match copy {
+                Some(copy) => {
+                    dup2(copy, fd)?;
+                    close(copy)?;
+                }
+                None => {
+                    let _ = close(fd);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// If `token` is a redirection operator, returns the redirections it
+// denotes, consuming its target from `tokens` where one is required.
+pub fn get_redirections<'a, I>(token: &Token, tokens: &mut I) -> Result<Option<Vec<Redirection>>>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    if !token.is_literal() {
+        return Ok(None);
+    }
+
+    let operator = match get_operator(token.text()) {
+        Some(operator) => operator,
+        None => return Ok(None),
+    };
+
+    let redirections = match operator {
+        Operator::ToFd(fd, target) => vec![Redirection::Fd { fd, target }],
+        Operator::ToFile(fd, mode) => {
+            let path = get_target(token.text(), tokens.next())?;
+            vec![Redirection::File { fd, path, mode }]
+        }
+        Operator::AllToFile(mode) => {
+            let path = get_target(token.text(), tokens.next())?;
+            vec![
+                Redirection::File { fd: 1, path, mode },
+                Redirection::Fd { fd: 2, target: 1 },
+            ]
+        }
+        Operator::HereString => match tokens.next() {
+            Some(target) => {
+                let mut text = target.selected_text().join(" ");
+                text.push('\n');
+                vec![Redirection::Text { fd: 0, text }]
+            }
+            None => return Err(anyhow!("<<<: Missing here-string")),
+        },
+    };
+
+    Ok(Some(redirections))
+}
+
+// A target must expand to exactly one word
+fn get_target(operator: &str, token: Option<&Token>) -> Result<String> {
+    match token {
+        Some(token) => {
+            let words: Vec<&String> = token
+                .selected_text()
+                .iter()
+                .filter(|x| !x.is_empty())
+                .collect();
+
+            if words.len() == 1 {
+                Ok(words[0].to_string())
+            } else {
+                Err(anyhow!("{}: Ambiguous redirection target", operator))
+            }
+        }
+        None => Err(anyhow!("{}: Missing redirection target", operator)),
+    }
+}
+
+pub fn is_redirection_operator(token: &Token) -> bool {
+    token.is_literal() && get_operator(token.text()).is_some()
+}
+
+fn get_operator(text: &str) -> Option<Operator> {
+    match text {
+        "&>" => return Some(Operator::AllToFile(OpenMode::Truncate)),
+        "&>>" => return Some(Operator::AllToFile(OpenMode::Append)),
+        "<<<" => return Some(Operator::HereString),
+        _ => {}
+    }
+
+    // Optional leading file descriptor
+    let digits = text.chars().take_while(|c| c.is_ascii_digit()).count();
+    let (fd, op) = text.split_at(digits);
+    let fd: Option<RawFd> = if fd.is_empty() {
+        None
+    } else {
+        Some(fd.parse().ok()?)
+    };
+
+    match op {
+        ">" => Some(Operator::ToFile(fd.unwrap_or(1), OpenMode::Truncate)),
+        ">>" => Some(Operator::ToFile(fd.unwrap_or(1), OpenMode::Append)),
+        "<" => Some(Operator::ToFile(fd.unwrap_or(0), OpenMode::Read)),
+        _ => {
+            let target = op.strip_prefix(">&")?;
+
+            if !target.is_empty() && target.chars().all(|c| c.is_ascii_digit()) {
+                Some(Operator::ToFd(fd.unwrap_or(1), target.parse().ok()?))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn get_operator_1() {
+        assert_eq!(
+            Some(Operator::ToFile(1, OpenMode::Truncate)),
+            get_operator(">")
+        );
+    }
+
+    #[test]
+    fn get_operator_2() {
+        assert_eq!(
+            Some(Operator::ToFile(1, OpenMode::Append)),
+            get_operator(">>")
+        );
+    }
+
+    #[test]
+    fn get_operator_3() {
+        assert_eq!(Some(Operator::ToFile(0, OpenMode::Read)), get_operator("<"));
+    }
+
+    #[test]
+    fn get_operator_4() {
+        assert_eq!(
+            Some(Operator::ToFile(2, OpenMode::Truncate)),
+            get_operator("2>")
+        );
+    }
+
+    #[test]
+    fn get_operator_5() {
+        assert_eq!(Some(Operator::ToFd(2, 1)), get_operator("2>&1"));
+    }
+
+    #[test]
+    fn get_operator_6() {
+        assert_eq!(
+            Some(Operator::AllToFile(OpenMode::Truncate)),
+            get_operator("&>")
+        );
+    }
+
+    #[test]
+    fn get_operator_7() {
+        assert_eq!(None, get_operator("file"));
+        assert_eq!(None, get_operator("2"));
+        assert_eq!(None, get_operator("2>&"));
+        assert_eq!(None, get_operator("=>"));
+    }
+
+    #[test]
+    fn get_operator_8() {
+        assert_eq!(Some(Operator::HereString), get_operator("<<<"));
+        assert_eq!(None, get_operator("<<"));
+    }
+
+    #[test]
+    fn get_redirections_1() {
+        let mut op = Token::new(">>".to_string()).unwrap();
+        let mut target = Token::new("file".to_string()).unwrap();
+        op.select().unwrap();
+        target.select().unwrap();
+
+        let rest = [target];
+        let mut tokens = rest.iter();
+
+        assert_eq!(
+            Some(vec![Redirection::File {
+                fd: 1,
+                path: "file".to_string(),
+                mode: OpenMode::Append
+            }]),
+            get_redirections(&op, &mut tokens).unwrap()
+        );
+    }
+
+    #[test]
+    fn get_redirections_2() {
+        let mut op = Token::new("'>'".to_string()).unwrap();
+        op.select().unwrap();
+
+        let rest = Vec::<Token>::new();
+        let mut tokens = rest.iter();
+
+        assert_eq!(None, get_redirections(&op, &mut tokens).unwrap());
+    }
+
+    #[test]
+    fn get_redirections_3() {
+        let mut op = Token::new(">".to_string()).unwrap();
+        op.select().unwrap();
+
+        let rest = Vec::<Token>::new();
+        let mut tokens = rest.iter();
+
+        assert!(get_redirections(&op, &mut tokens).is_err());
+    }
+
+    #[test]
+    fn get_redirections_4() {
+        let mut op = Token::new("<<<".to_string()).unwrap();
+        let mut target = Token::new("\"one two\"".to_string()).unwrap();
+        op.select().unwrap();
+        target.select().unwrap();
+
+        let rest = [target];
+        let mut tokens = rest.iter();
+
+        assert_eq!(
+            Some(vec![Redirection::Text {
+                fd: 0,
+                text: "one two\n".to_string()
+            }]),
+            get_redirections(&op, &mut tokens).unwrap()
+        );
+    }
+}
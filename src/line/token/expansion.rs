@@ -1,22 +1,40 @@
 use crate::line::Line;
+use crate::shell::variables::{get_positional, get_special_variable};
 use crate::shell::Shell;
 use crate::sources::{subshell::SubshellSource, SourceKind};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use regex::Regex;
 
+use super::terminal::{has_terminal_expansion, terminal_expand_text};
 use super::{Expansion, Token};
 
 use std::env;
-use std::fs::File;
+use std::fs::{read_dir, File};
 use std::io::Read;
 use std::os::unix::io::FromRawFd;
+use std::path::PathBuf;
 
-use nix::sys::wait::wait;
+use nix::sys::wait::waitpid;
 use nix::unistd::{close, dup2, fork, pipe, ForkResult};
 
 pub fn expand(word: &mut Token, smsh: &mut Shell) -> Result<()> {
     match word.expansion {
         Expansion::Variable => {
-            if let Some(val) = smsh.get_user_variable(&word.text) {
+            if let Some((n, rest)) = get_positional(&word.text) {
+                let args = smsh.arguments();
+                let args: Vec<String> = if rest {
+                    args.iter().skip(n).cloned().collect()
+                } else {
+                    args.get(n).cloned().into_iter().collect()
+                };
+
+                word.text = args.join(" ");
+                if rest {
+                    word.words = Some(args);
+                }
+            } else if let Some(val) = get_special_variable(smsh, &word.text) {
+                word.text = val;
+            } else if let Some(val) = smsh.get_user_variable(&word.text) {
                 word.text = val;
             } else {
                 word.text.clear();
@@ -38,6 +56,18 @@ pub fn expand(word: &mut Token, smsh: &mut Shell) -> Result<()> {
 
             Ok(())
         }
+        Expansion::Filename => {
+            let paths = filename_expand(&word.text)?;
+            word.text = paths.join(" ");
+            word.words = Some(paths);
+
+            Ok(())
+        }
+        Expansion::Terminal => {
+            word.text = terminal_expand_text(smsh, &word.text)?;
+
+            Ok(())
+        }
         Expansion::None => Ok(()),
     }
 }
@@ -57,6 +87,13 @@ pub fn get_expansion(text: &str) -> (String, Expansion) {
         let mut s = text[2..].to_string();
         s.pop();
         (s, Expansion::Environment)
+    } else if text.starts_with("f{") && text.ends_with('}') {
+        let mut s = text[2..].to_string();
+        s.pop();
+        (s, Expansion::Filename)
+    } else if has_terminal_expansion(text) {
+        // Kept whole, as terminal expansions may follow one another
+        (text.to_string(), Expansion::Terminal)
     } else {
         (text.to_string(), Expansion::None)
     }
@@ -66,11 +103,9 @@ pub fn subshell_expand(smsh: &mut Shell, line: &str) -> Result<String> {
     let (rd, wr) = pipe()?;
 
     match unsafe { fork()? } {
-        ForkResult::Parent { child: _, .. } => {
+        ForkResult::Parent { child, .. } => {
             close(wr)?;
 
-            wait()?;
-
             let mut buf = String::new();
 
             unsafe {
@@ -78,9 +113,12 @@ pub fn subshell_expand(smsh: &mut Shell, line: &str) -> Result<String> {
                 rd.read_to_string(&mut buf)?;
             }
 
+            waitpid(child, None)?;
+
             Ok(buf)
         }
         ForkResult::Child => {
+            smsh.become_child();
             smsh.clear_sources();
 
             close(rd)?;
@@ -99,3 +137,121 @@ pub fn subshell_expand(smsh: &mut Shell, line: &str) -> Result<String> {
         }
     }
 }
+
+// Splits `pattern` on `/`, and matches each component as a regex against
+// the entries of the directories matched so far.  `.` and `..` are taken
+// literally.  Yields matching paths in alphabetical order.
+pub fn filename_expand(pattern: &str) -> Result<Vec<String>> {
+    let (root, relative) = match pattern.strip_prefix('/') {
+        Some(_) => (PathBuf::from("/"), false),
+        None => (PathBuf::new(), true),
+    };
+
+    let components: Vec<&str> = pattern.split('/').filter(|x| !x.is_empty()).collect();
+    let mut paths = vec![root];
+
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i + 1 == components.len();
+        let mut matches = Vec::<PathBuf>::new();
+
+        if *component == "." || *component == ".." {
+            for path in paths {
+                matches.push(path.join(component));
+            }
+            paths = matches;
+            continue;
+        }
+
+        let re = Regex::new(&format!("^(?:{})$", component))
+            .map_err(|e| anyhow!("f{{{}}}: Invalid expression:\n{}", pattern, e))?;
+
+        for path in &paths {
+            let dir = if relative && path.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                path.clone()
+            };
+
+            let entries = match read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue, // Not a directory, or unreadable
+            };
+
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+
+                if re.is_match(&name.to_string_lossy()) && (is_last || entry.path().is_dir()) {
+                    matches.push(path.join(name));
+                }
+            }
+        }
+
+        paths = matches;
+    }
+
+    let mut paths: Vec<String> = paths
+        .iter()
+        .filter(|x| !x.as_os_str().is_empty())
+        .map(|x| x.to_string_lossy().to_string())
+        .collect();
+
+    if paths.is_empty() {
+        return Err(anyhow!("f{{{}}}: No matches found", pattern));
+    }
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    #[test]
+    fn get_expansion_1() {
+        let text = "f{.*\\.log}";
+        assert_eq!(
+            (".*\\.log".to_string(), Expansion::Filename),
+            get_expansion(text)
+        );
+    }
+
+    #[test]
+    fn get_expansion_2() {
+        let text = "t{red}t{user}t{reset}";
+        assert_eq!((text.to_string(), Expansion::Terminal), get_expansion(text));
+
+        let text = "t{user}@";
+        assert_eq!((text.to_string(), Expansion::Terminal), get_expansion(text));
+
+        // Only at the start of a word
+        for text in ["at{x}", "-t{a}", "foot{x}", "[t{user}]"] {
+            assert_eq!((text.to_string(), Expansion::None), get_expansion(text));
+        }
+    }
+
+    #[test]
+    fn filename_expand_1() {
+        let dir = env::temp_dir().join(format!("smsh_filename_expand_{}", std::process::id()));
+        create_dir_all(dir.join("sub")).unwrap();
+
+        for file in ["b.log", "a.log", "c.txt", "sub/d.log"] {
+            write(dir.join(file), "").unwrap();
+        }
+
+        let dir_text = dir.to_string_lossy().to_string();
+
+        let found = filename_expand(&format!("{}/.*\\.log", dir_text));
+        let found_sub = filename_expand(&format!("{}/s.*/.*", dir_text));
+        let not_found = filename_expand(&format!("{}/.*\\.rs", dir_text));
+
+        remove_dir_all(&dir).unwrap();
+
+        let expected = vec![format!("{}/a.log", dir_text), format!("{}/b.log", dir_text)];
+        assert_eq!(expected, found.unwrap());
+        assert_eq!(vec![format!("{}/sub/d.log", dir_text)], found_sub.unwrap());
+        assert!(not_found.is_err());
+    }
+}
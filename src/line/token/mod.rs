@@ -9,10 +9,14 @@ use unicode_segmentation::UnicodeSegmentation;
 
 use std::cmp::min;
 
+use super::diagnostic::{Diagnostic, Span};
+
 mod expansion;
 use expansion::*;
 mod selection;
-use selection::{get_selection, Selection};
+use selection::{get_selection, InvalidSelection, Selection};
+mod terminal;
+pub use terminal::format_local_time;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Quote {
@@ -27,6 +31,8 @@ pub enum Expansion {
     Variable,
     Environment,
     Subshell,
+    Filename,
+    Terminal,
 }
 
 // TODO:  Eliminate 'separated_text' field;
@@ -39,6 +45,8 @@ pub struct Token {
     selection: Selection,
     separated_text: Vec<String>,
     selected_text: Vec<String>,
+    words: Option<Vec<String>>, // Set by expansions yielding a list of words
+    span: Span,                 // Within the line's raw text
 }
 
 impl Token {
@@ -55,15 +63,15 @@ impl Token {
             Quote::Unquoted | Quote::DoubleQuoted => get_expansion(&text),
         };
 
-        let separated_text = vec![text.clone()];
-
         let token = Token {
             text,
             quote,
             expansion,
             selection,
-            separated_text,
+            separated_text: Vec::<String>::new(),
+            words: None,
             selected_text: Vec::<String>::new(),
+            span: Span::default(),
         };
 
         Ok(token)
@@ -77,15 +85,62 @@ impl Token {
         }
     }
 
+    // True if token is neither quoted nor expanded, so that it
+    // may be interpreted as an operator
+    pub fn is_literal(&self) -> bool {
+        self.quote == Quote::Unquoted && self.expansion == Expansion::None
+    }
+
+    pub fn is_background_operator(&self) -> bool {
+        self.is_literal() && self.text == "&"
+    }
+
+    pub fn is_here_doc_operator(&self) -> bool {
+        self.is_literal() && self.text == "<<"
+    }
+
     pub fn is_pipe_operator(&self) -> bool {
-        self.selected_text.len() == 1 && self.selected_text[0] == "|"
+        self.is_literal() && self.text == "|"
+    }
+
+    pub fn is_and_operator(&self) -> bool {
+        self.is_literal() && self.text == "and"
+    }
+
+    pub fn is_or_operator(&self) -> bool {
+        self.is_literal() && self.text == "or"
+    }
+
+    pub fn is_not_operator(&self) -> bool {
+        self.is_literal() && self.text == "not"
     }
 
     pub fn selected_text(&self) -> &Vec<String> {
         &self.selected_text
     }
 
+    // Breaks expanded text into words.  Only unquoted expansions
+    // undergo separation; a list of words is kept as is.
+    fn separate(&mut self) {
+        self.separated_text = match (self.quote, self.expansion) {
+            (Quote::Unquoted, _) if self.words.is_some() => self.words.clone().unwrap(),
+            (Quote::Unquoted, Expansion::None)
+            | (Quote::SingleQuoted, _)
+            | (Quote::DoubleQuoted, _) => {
+                vec![self.text.clone()]
+            }
+            (Quote::Unquoted, _) => self
+                .text
+                .split_whitespace()
+                .map(|x| x.to_string())
+                .collect(),
+        };
+    }
+
     pub fn select(&mut self) -> Result<()> {
+        self.separate();
+        self.selected_text.clear();
+
         match &self.selection {
             Selection::Index(n) => {
                 if *n < self.separated_text.len() {
@@ -94,9 +149,9 @@ impl Token {
                 }
             }
             Selection::Slice(n, m) => {
-                if self.separated_text.len() > 0 && *n < self.separated_text.len() {
+                if *n < self.separated_text.len() {
                     if *m > *n {
-                        let min = min(self.separated_text.len() - 1, *m);
+                        let min = min(self.separated_text.len(), *m);
 
                         for w in &self.separated_text[*n..min] {
                             self.selected_text.push(w.to_string());
@@ -130,6 +185,22 @@ impl Token {
         Ok(())
     }
 
+    pub fn quote(&self) -> Quote {
+        self.quote
+    }
+
+    pub fn expansion(&self) -> Expansion {
+        self.expansion
+    }
+
+    pub fn has_selection(&self) -> bool {
+        self.selection != Selection::All
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
     pub fn text(&self) -> &str {
         &self.text
     }
@@ -164,7 +235,8 @@ fn get_quote(text: &str) -> Result<(String, Quote)> {
 
 // Breaks `rawline` into parts according to quoting rules, yielding tokens.
 // Quotes and escapes are preserved; unquoted whitespace is removed
-// Selection remains appended to part.
+// Selection remains appended to part.  Each token keeps its span
+// within `rawline`, to which errors are attributed.
 pub fn get_tokens(rawline: &str) -> Result<Vec<Token>> {
     #[derive(PartialEq, Eq, Clone, Copy)]
     enum State {
@@ -181,30 +253,42 @@ pub fn get_tokens(rawline: &str) -> Result<Vec<Token>> {
     let mut state = State::Unquoted;
     let mut escaped_state: Option<State> = None;
 
-    for grapheme in rawline.graphemes(true) {
+    let mut start = 0; // Of `part`
+    let mut opened_at = 0; // Of the quote or brace last opened
+    let mut escaped_at = 0;
+
+    for (i, grapheme) in rawline.grapheme_indices(true) {
+        if part.is_empty() {
+            start = i;
+        }
+
         match state {
             State::Unquoted => match grapheme {
                 " " | "\t" => {
                     if !part.is_empty() {
-                        tokens.push(Token::new(part)?);
+                        tokens.push(new_token(rawline, part, Span::new(start, i))?);
                         part = String::new();
                     }
                 }
                 "\'" => {
                     part.push_str(grapheme);
+                    opened_at = i;
                     state = State::SingleQuoted;
                 }
                 "\"" => {
                     part.push_str(grapheme);
+                    opened_at = i;
                     state = State::DoubleQuoted;
                 }
                 "\\" => {
                     part.push_str(grapheme);
+                    escaped_at = i;
                     escaped_state = Some(State::Unquoted);
                     state = State::Escaped;
                 }
                 "{" => {
                     part.push_str(grapheme);
+                    opened_at = i;
                     state = State::Expansion;
                 }
                 _ => {
@@ -222,6 +306,7 @@ pub fn get_tokens(rawline: &str) -> Result<Vec<Token>> {
                 if grapheme == "\"" {
                     state = State::Unquoted;
                 } else if grapheme == "\\" {
+                    escaped_at = i;
                     escaped_state = Some(State::DoubleQuoted);
                     state = State::Escaped;
                 }
@@ -233,6 +318,7 @@ pub fn get_tokens(rawline: &str) -> Result<Vec<Token>> {
             State::Expansion => {
                 part.push_str(grapheme);
                 if grapheme == "\\" {
+                    escaped_at = i;
                     escaped_state = Some(State::Expansion);
                     state = State::Escaped;
                 } else if grapheme == "}" {
@@ -241,13 +327,44 @@ pub fn get_tokens(rawline: &str) -> Result<Vec<Token>> {
             }
         }
     }
+
+    let unmatched = |at: usize, label: &str| {
+        let span = Span::new(at, at + 1);
+        Err(Diagnostic::new(rawline, span, label).into())
+    };
+
+    match state {
+        State::SingleQuoted => return unmatched(opened_at, "Unmatched single quote"),
+        State::DoubleQuoted => return unmatched(opened_at, "Unmatched double quote"),
+        State::Expansion => return unmatched(opened_at, "Unmatched expansion brace"),
+        State::Escaped => return unmatched(escaped_at, "Line terminates in escape character"),
+        State::Unquoted => {}
+    }
+
     if !part.is_empty() {
-        tokens.push(Token::new(part)?);
+        tokens.push(new_token(rawline, part, Span::new(start, rawline.len()))?);
     }
 
     Ok(tokens)
 }
 
+// Errors in creating a token are attributed to its span
+fn new_token(rawline: &str, text: String, span: Span) -> Result<Token> {
+    match Token::new(text) {
+        Ok(mut token) => {
+            token.span = span;
+            Ok(token)
+        }
+        Err(e) if e.is::<InvalidSelection>() => {
+            let note = "help: Selections take the form [n], [n..m], [n..] or [..m]";
+            Err(Diagnostic::new(rawline, span, &e.to_string())
+                .with_note(note.to_string())
+                .into())
+        }
+        Err(e) => Err(Diagnostic::new(rawline, span, &e.to_string()).into()),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -263,7 +380,9 @@ mod test {
             expansion: Expansion::None,
             selection: Selection::All,
             separated_text: Vec::<String>::new(),
+            words: None,
             selected_text: Vec::<String>::new(),
+            span: Span::default(),
         };
 
         assert_eq!(token, Token::new(cmd).unwrap());
@@ -279,7 +398,9 @@ mod test {
             expansion: Expansion::Variable,
             selection: Selection::All,
             separated_text: Vec::<String>::new(),
+            words: None,
             selected_text: Vec::<String>::new(),
+            span: Span::default(),
         };
 
         assert_eq!(token, Token::new(cmd).unwrap());
@@ -295,7 +416,9 @@ mod test {
             expansion: Expansion::Subshell,
             selection: Selection::All,
             separated_text: Vec::<String>::new(),
+            words: None,
             selected_text: Vec::<String>::new(),
+            span: Span::default(),
         };
 
         assert_eq!(token, Token::new(cmd).unwrap());
@@ -311,7 +434,9 @@ mod test {
             expansion: Expansion::Subshell,
             selection: Selection::All,
             separated_text: Vec::<String>::new(),
+            words: None,
             selected_text: Vec::<String>::new(),
+            span: Span::default(),
         };
 
         assert_eq!(token, Token::new(cmd).unwrap());
@@ -327,7 +452,9 @@ mod test {
             expansion: Expansion::Subshell,
             selection: Selection::Index(1),
             separated_text: Vec::<String>::new(),
+            words: None,
             selected_text: Vec::<String>::new(),
+            span: Span::default(),
         };
 
         assert_eq!(token, Token::new(cmd).unwrap());
@@ -341,9 +468,11 @@ mod test {
             text: "{cmd}".to_string(),
             quote: Quote::Unquoted,
             expansion: Expansion::Subshell,
-            selection: Selection::Slice(1, 0),
+            selection: Selection::GreaterThan(1),
             separated_text: Vec::<String>::new(),
+            words: None,
             selected_text: Vec::<String>::new(),
+            span: Span::default(),
         };
 
         assert_eq!(token, Token::new(cmd).unwrap());
@@ -359,7 +488,9 @@ mod test {
             expansion: Expansion::None,
             selection: Selection::All,
             separated_text: Vec::<String>::new(),
+            words: None,
             selected_text: Vec::<String>::new(),
+            span: Span::default(),
         };
 
         assert_eq!(token, Token::new(cmd).unwrap());
@@ -368,8 +499,8 @@ mod test {
     #[test]
     fn expand_1() {
         // Replace 'cmd' with 'cat'
-        let mut smsh = Shell::new();
-        smsh.insert_user_variable("cmd".to_string(), "cat".to_string());
+        let mut smsh = Shell::new(false);
+        smsh.insert_user_variable("cmd".to_string(), "cat".to_string(), false);
 
         let mut word = Token::new("{cmd}".to_string()).unwrap();
 
@@ -382,12 +513,49 @@ mod test {
     fn get_tokens1() {
         let line = "echo one two three";
         let tokens = vec![
-            Token::new("echo".to_string()).unwrap(),
-            Token::new("one".to_string()).unwrap(),
-            Token::new("two".to_string()).unwrap(),
-            Token::new("three".to_string()).unwrap(),
+            new_token(line, "echo".to_string(), Span::new(0, 4)).unwrap(),
+            new_token(line, "one".to_string(), Span::new(5, 8)).unwrap(),
+            new_token(line, "two".to_string(), Span::new(9, 12)).unwrap(),
+            new_token(line, "three".to_string(), Span::new(13, 18)).unwrap(),
         ];
 
         assert_eq!(tokens, get_tokens(line).unwrap());
     }
+
+    #[test]
+    fn select_1() {
+        // The end of a slice is exclusive, and may lie past the last word
+        let mut token = Token::new("{words}[1..3]".to_string()).unwrap();
+        token.text = "zero one two".to_string();
+
+        token.select().unwrap();
+        assert_eq!(vec!["one", "two"], *token.selected_text());
+
+        // Selecting again starts afresh
+        token.select().unwrap();
+        assert_eq!(vec!["one", "two"], *token.selected_text());
+    }
+
+    #[test]
+    fn get_tokens2() {
+        let cases = [
+            (
+                "echo {PATH",
+                "echo {PATH\n           ^ Unmatched expansion brace",
+            ),
+            (
+                "echo 'one",
+                "echo 'one\n           ^ Unmatched single quote",
+            ),
+            (
+                "cat x[a]",
+                "cat x[a]\n          ^^^^ Invalid selection a\n      \
+                 = help: Selections take the form [n], [n..m], [n..] or [..m]",
+            ),
+        ];
+
+        for (line, expected) in cases {
+            assert_eq!(expected, get_tokens(line).unwrap_err().to_string());
+        }
+    }
 }
@@ -2,7 +2,10 @@
 //      This should be redone, making gratuitous use of enums and helper functions.
 //
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+
+use std::error::Error;
+use std::fmt;
 
 // TODO: Slice(Option<usize>, Option<usize>)
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -15,6 +18,18 @@ pub enum Selection {
     LessThan(usize),
 }
 
+// A selector that fits none of the forms below, holding its text
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InvalidSelection(String);
+
+impl fmt::Display for InvalidSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid selection {}", self.0)
+    }
+}
+
+impl Error for InvalidSelection {}
+
 // Returns text with selector removed.
 pub fn get_selection(text: &str) -> Result<(String, Selection)> {
     if let Some((text, selection_text)) = get_selector(text) {
@@ -121,7 +136,7 @@ fn determine_selection(selection_text: &str) -> Result<Selection> {
     }
 
     if state == State::Invalid {
-        Err(anyhow!("Invalid selection {}", selection_text))
+        Err(InvalidSelection(selection_text.to_string()).into())
     } else if let Some(first_num) = first_num {
         if let Some(second_num) = second_num {
             Ok(Selection::Slice(first_num, second_num))
@@ -254,5 +269,8 @@ mod test {
     #[test]
     fn determine_selection_11() {
         assert!(determine_selection("a...b").is_err());
+        assert!(determine_selection("a...b")
+            .unwrap_err()
+            .is::<InvalidSelection>());
     }
 }
@@ -0,0 +1,172 @@
+// Terminal expansion, `t{}`, exists mostly for the sake of prompts.
+// See doc/expansion.md.
+
+use anyhow::{anyhow, Result};
+use nix::unistd::{gethostname, getuid, User};
+
+use crate::shell::Shell;
+
+use std::env;
+use std::ffi::CStr;
+use std::path::Path;
+
+const COLORS: [(&str, &str); 10] = [
+    ("black", "\x1b[30m"),
+    ("red", "\x1b[31m"),
+    ("green", "\x1b[32m"),
+    ("yellow", "\x1b[33m"),
+    ("blue", "\x1b[34m"),
+    ("magenta", "\x1b[35m"),
+    ("cyan", "\x1b[36m"),
+    ("white", "\x1b[37m"),
+    ("bold", "\x1b[1m"),
+    ("reset", "\x1b[0m"),
+];
+
+pub fn terminal_expand(smsh: &Shell, key: &str) -> Result<String> {
+    if let Some((_, code)) = COLORS.iter().find(|(name, _)| *name == key) {
+        return Ok(code.to_string());
+    }
+
+    match key {
+        "bell" => Ok("\x07".to_string()),
+        "date" => Ok(local_time("%a %b %d")),
+        "time" => Ok(local_time("%H:%M:%S")),
+        "user" => Ok(user()),
+        "host" => Ok(host()),
+        "cwd" => Ok(cwd()),
+        "short_cwd" => Ok(short_cwd()),
+        "rv" => Ok(smsh.rv().to_string()),
+        "prompt_char" => Ok(if getuid().is_root() { "#" } else { "$" }.to_string()),
+        _ => Err(anyhow!("t{{{}}}: Unknown terminal expansion", key)),
+    }
+}
+
+// Expands the `t{key}` beginning `text`, and each one directly following
+// it, keeping whatever text comes after them
+pub fn terminal_expand_text(smsh: &Shell, text: &str) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+
+    while has_terminal_expansion(rest) {
+        let end = rest.find('}').unwrap();
+
+        expanded.push_str(&terminal_expand(smsh, &rest[2..end])?);
+        rest = &rest[end + 1..];
+    }
+
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+// Like the other expansions, a terminal expansion begins its word
+pub fn has_terminal_expansion(text: &str) -> bool {
+    text.starts_with("t{") && text.contains('}')
+}
+
+extern "C" {
+    // Not bound by the libc crate
+    fn strftime(
+        s: *mut libc::c_char,
+        max: libc::size_t,
+        format: *const libc::c_char,
+        tm: *const libc::tm,
+    ) -> libc::size_t;
+}
+
+// Formats the current local time according to strftime(3)
+fn local_time(format: &str) -> String {
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    format_local_time(now, format)
+}
+
+// Formats `time`, in seconds since the epoch, as local time
+pub fn format_local_time(time: libc::time_t, format: &str) -> String {
+    let mut buf = [0u8; 64];
+    let format = format!("{}\0", format);
+
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return String::new();
+        }
+
+        let len = strftime(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            format.as_ptr() as *const libc::c_char,
+            &tm,
+        );
+
+        String::from_utf8_lossy(&buf[..len]).to_string()
+    }
+}
+
+fn user() -> String {
+    match User::from_uid(getuid()) {
+        Ok(Some(user)) => user.name,
+        _ => env::var("USER").unwrap_or_default(),
+    }
+}
+
+fn host() -> String {
+    let mut buf = [0u8; 256];
+
+    gethostname(&mut buf)
+        .map(CStr::to_string_lossy)
+        .map(|x| x.to_string())
+        .unwrap_or_default()
+}
+
+fn cwd() -> String {
+    match env::current_dir() {
+        Ok(path) => path.to_string_lossy().to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+// The last component of the current working directory, or `~` at home
+fn short_cwd() -> String {
+    let cwd = cwd();
+
+    if env::var("HOME").map(|x| x == cwd).unwrap_or(false) {
+        return "~".to_string();
+    }
+
+    match Path::new(&cwd).file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => cwd,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn terminal_expand_1() {
+        let smsh = Shell::new(false);
+        assert_eq!("\x1b[31m", terminal_expand(&smsh, "red").unwrap());
+        assert_eq!("\x07", terminal_expand(&smsh, "bell").unwrap());
+        assert!(terminal_expand(&smsh, "nonsense").is_err());
+    }
+
+    #[test]
+    fn terminal_expand_text_1() {
+        let smsh = Shell::new(false);
+        assert_eq!(
+            "\x1b[31m\x1b[1mok t{reset}",
+            terminal_expand_text(&smsh, "t{red}t{bold}ok t{reset}").unwrap()
+        );
+        assert!(terminal_expand_text(&smsh, "t{red}t{nonsense}").is_err());
+    }
+
+    #[test]
+    fn local_time_1() {
+        let time = local_time("%H:%M:%S");
+        assert_eq!(8, time.len());
+        assert_eq!(Some(':'), time.chars().nth(2));
+    }
+}
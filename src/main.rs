@@ -3,16 +3,71 @@ mod line;
 mod shell;
 mod sources;
 
+use nix::unistd::isatty;
+
 use shell::Shell;
+use sources::basic_tty::{BasicTty, Unbuffered};
+use sources::script::Script;
+use sources::SourceKind;
+
+use std::env;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::process::exit;
 
+// smsh [-c line | script [args...]]
 fn main() {
-    let mut smsh = Shell::new();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let mut smsh = build_shell(args);
 
     while let Err(e) = smsh.run() {
         eprintln!("smsh: {}", e);
 
+        // Outside of the terminal, an error ends the script
+        if !smsh.is_interactive() && smsh.rv() == 0 {
+            smsh.set_rv(1);
+        }
+
         smsh.backtrace();
     }
 
-    std::process::exit(smsh.rv());
+    exit(smsh.rv());
+}
+
+fn build_shell(args: Vec<String>) -> Shell {
+    match args.first().map(|x| x.as_str()) {
+        Some("-c") => {
+            if args.len() != 2 {
+                eprintln!("smsh: -c: Expected a single line");
+                exit(2);
+            }
+
+            let mut smsh = Shell::new(false);
+            let input = Box::new(Cursor::new(args[1].clone()));
+            smsh.push_source(BasicTty::build_source(input, SourceKind::Command));
+            smsh
+        }
+        // The script's name and arguments become {0}, {1}, ...
+        Some(path) => {
+            let mut smsh = Shell::new(false);
+
+            match Script::build_source(PathBuf::from(path), Some(args.clone())) {
+                Ok(script) => smsh.push_source(script),
+                Err(e) => {
+                    eprintln!("smsh: {}: {}", path, e);
+                    exit(127);
+                }
+            }
+
+            smsh
+        }
+        None if isatty(0).unwrap_or(false) => Shell::new(true),
+        None => {
+            let mut smsh = Shell::new(false);
+            let input = Unbuffered::build_input(0);
+            smsh.push_source(BasicTty::build_source(input, SourceKind::Stdin));
+            smsh
+        }
+    }
 }
@@ -0,0 +1,224 @@
+// External commands are resolved on PATH by the shell itself, before
+// anything is forked.  Resolutions are cached until PATH changes, as are
+// the names of the executables on PATH, offered when a command is not
+// found, until one of its directories changes.
+
+use nix::unistd::{access, AccessFlags};
+
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub struct Commands {
+    path: Option<String>, // PATH, as of the cached resolutions
+    cache: HashMap<String, PathBuf>,
+    names: Vec<String>,
+    names_mtimes: Option<Vec<Option<SystemTime>>>, // Of PATH's directories, as of `names`
+}
+
+impl Commands {
+    pub fn new() -> Self {
+        Commands {
+            path: None,
+            cache: HashMap::new(),
+            names: vec![],
+            names_mtimes: None,
+        }
+    }
+
+    // Names of the executables on PATH
+    pub fn names(&mut self) -> &[String] {
+        self.names_in(env::var("PATH").ok())
+    }
+
+    fn names_in(&mut self, path: Option<String>) -> &[String] {
+        self.set_path(path);
+
+        let mtimes: Vec<Option<SystemTime>> = path_dirs(self.path.as_deref())
+            .map(|x| x.metadata().and_then(|x| x.modified()).ok())
+            .collect();
+
+        if self.names_mtimes.as_ref() != Some(&mtimes) {
+            self.names = list_commands(self.path.as_deref());
+            self.names_mtimes = Some(mtimes);
+        }
+
+        &self.names
+    }
+
+    fn set_path(&mut self, path: Option<String>) {
+        if path != self.path {
+            self.cache.clear();
+            self.names_mtimes = None;
+            self.path = path;
+        }
+    }
+
+    // A command containing a slash is not looked up on PATH
+    pub fn resolve(&mut self, command: &str) -> Option<PathBuf> {
+        if command.contains('/') {
+            let path = PathBuf::from(command);
+            return if path.exists() { Some(path) } else { None };
+        }
+
+        self.set_path(env::var("PATH").ok());
+
+        // A cached resolution is good only so long as it exists
+        if let Some(resolved) = self.cache.get(command) {
+            if is_executable(resolved) {
+                return Some(resolved.clone());
+            }
+            self.cache.remove(command);
+        }
+
+        let resolved = path_dirs(self.path.as_deref())
+            .map(|x| x.join(command))
+            .find(|x| is_executable(x))?;
+
+        self.cache.insert(command.to_string(), resolved.clone());
+
+        Some(resolved)
+    }
+}
+
+fn path_dirs(path: Option<&str>) -> impl Iterator<Item = PathBuf> + '_ {
+    path.unwrap_or_default()
+        .split(':')
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.is_file() && access(path, AccessFlags::X_OK).is_ok()
+}
+
+// Names of the executables on PATH
+pub fn path_commands() -> Vec<String> {
+    list_commands(env::var("PATH").ok().as_deref())
+}
+
+fn list_commands(path: Option<&str>) -> Vec<String> {
+    let mut commands = Vec::<String>::new();
+
+    for dir in path_dirs(path) {
+        if let Ok(entries) = dir.read_dir() {
+            for entry in entries.flatten() {
+                if is_executable(&entry.path()) {
+                    commands.push(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+        }
+    }
+
+    commands
+}
+
+// The candidate nearest to `name` by edit distance, if near enough
+// to be a likely misspelling
+pub fn suggest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = match name.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    };
+
+    candidates
+        .filter(|x| *x != name)
+        .map(|x| (edit_distance(name, x), x))
+        .filter(|(distance, _)| *distance <= max_distance && *distance > 0)
+        .min()
+        .map(|(_, x)| x)
+}
+
+// Levenshtein distance, counting a transposition of adjacent
+// characters as a single edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn edit_distance_1() {
+        assert_eq!(0, edit_distance("clear", "clear"));
+        assert_eq!(1, edit_distance("celar", "clear"));
+        assert_eq!(1, edit_distance("sl", "ls"));
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+    }
+
+    #[test]
+    fn suggest_1() {
+        let candidates = ["clear", "cal", "cat", "chdir"];
+        assert_eq!(Some("clear"), suggest("celar", candidates.into_iter()));
+        assert_eq!(None, suggest("xyzzy", candidates.into_iter()));
+    }
+
+    #[test]
+    fn resolve_1() {
+        let mut commands = Commands::new();
+        let resolved = commands.resolve("sh").unwrap();
+        assert!(resolved.ends_with("sh"));
+        assert!(commands.cache.contains_key("sh"));
+        assert!(commands.resolve("no-such-command-for-smsh").is_none());
+    }
+
+    #[test]
+    fn names_1() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("smsh-commands-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let add = |name: &str| {
+            let file = dir.join(name);
+            fs::write(&file, "").unwrap();
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+
+        let path = Some(dir.to_string_lossy().to_string());
+        let mut commands = Commands::new();
+
+        add("smsh-tool");
+        assert_eq!(vec!["smsh-tool"], commands.names_in(path.clone()));
+
+        // Listed anew once the directory changes
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        add("smsh-other");
+        let mut names = commands.names_in(path).to_vec();
+        names.sort();
+        assert_eq!(vec!["smsh-other", "smsh-tool"], names);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
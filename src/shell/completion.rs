@@ -0,0 +1,268 @@
+// Completion rules for external commands, declared by the `complete`
+// builtin.  Rules are kept per command path, that is, a command and the
+// subcommands leading to the word being completed:  `git`, `git remote`,
+// `git remote add`.  See doc/completion.md.
+
+use super::Shell;
+use crate::sources::user_function::UserFunction;
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::waitpid;
+use nix::unistd::{close, dup2, fork, pipe, read, setpgid, ForkResult, Pid};
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// How long a completion function is given to finish
+const FUNCTION_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Argument {
+    File,
+    Directory,
+    Words(Vec<String>),
+    Function(String), // Whose output gives the words
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Rule {
+    pub subcommands: Vec<String>,
+    pub flags: Vec<String>,
+    pub argument: Option<Argument>,
+}
+
+#[derive(Clone, Default)]
+pub struct Completions {
+    rules: BTreeMap<Vec<String>, Rule>,
+}
+
+// Where a line stands with respect to the rules of its command
+#[derive(PartialEq, Eq, Debug)]
+pub struct Position<'a> {
+    pub rule: &'a Rule,
+    pub path: Vec<String>,
+    pub after_argument: bool, // Whether an argument came before the cursor
+}
+
+impl Completions {
+    // Changes the rule for `path`, making each of its subcommands
+    // a subcommand of the path before it
+    pub fn update(&mut self, path: &[String], update: impl FnOnce(&mut Rule)) {
+        for i in 1..path.len() {
+            let parent = self.rules.entry(path[..i].to_vec()).or_default();
+
+            if !parent.subcommands.contains(&path[i]) {
+                parent.subcommands.push(path[i].clone());
+            }
+        }
+
+        update(self.rules.entry(path.to_vec()).or_default());
+    }
+
+    // Removes every rule for `command`, returning whether there were any
+    pub fn remove(&mut self, command: &str) -> bool {
+        let len = self.rules.len();
+        self.rules.retain(|path, _| path[0] != command);
+        self.rules.len() != len
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = (&Vec<String>, &Rule)> {
+        self.rules.iter()
+    }
+
+    // Follows `words`, a command and the words after it, through the
+    // subcommands of its rules.  A command given by its path is known by
+    // its name.  Flags are passed over.
+    pub fn position(&self, words: &[&str]) -> Option<Position<'_>> {
+        let command = Path::new(words.first()?).file_name()?.to_string_lossy();
+
+        let mut path = vec![command.to_string()];
+        let mut rule = self.rules.get(&path)?;
+        let mut after_argument = false;
+
+        for word in &words[1..] {
+            if word.starts_with('-') {
+                continue;
+            }
+
+            if !after_argument && rule.subcommands.iter().any(|x| x == word) {
+                path.push(word.to_string());
+                rule = self.rules.get(&path)?;
+            } else {
+                after_argument = true;
+            }
+        }
+
+        Some(Position {
+            rule,
+            path,
+            after_argument,
+        })
+    }
+}
+
+// Runs `function` with `arguments` in a shell of its own, holding the
+// given functions and variables, and collects the words it prints.
+// Its errors are discarded, lest they garble the line being edited, and
+// it reads from /dev/null, as the terminal belongs to the line editor.
+// Whatever it has printed after FUNCTION_TIMEOUT is taken, and it is
+// killed, along with anything it started.
+pub fn function_words(
+    function: &str,
+    arguments: Vec<String>,
+    functions: &[UserFunction],
+    variables: &[(String, String)],
+) -> Vec<String> {
+    let func = match functions.iter().find(|x| x.name() == function) {
+        Some(func) => func.clone(),
+        None => return vec![],
+    };
+
+    let (rd, wr) = match pipe() {
+        Ok(fds) => fds,
+        Err(_) => return vec![],
+    };
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child, .. }) => {
+            // Set by both parent and child, as in Job::push
+            let _ = setpgid(child, child);
+            let _ = close(wr);
+
+            let output = read_until(rd, Instant::now() + FUNCTION_TIMEOUT);
+            let _ = close(rd);
+
+            let _ = kill(Pid::from_raw(-child.as_raw()), Signal::SIGKILL);
+            let _ = waitpid(child, None);
+
+            String::from_utf8_lossy(&output)
+                .split_whitespace()
+                .map(String::from)
+                .collect()
+        }
+        Ok(ForkResult::Child) => {
+            let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+
+            let _ = close(rd);
+            let _ = dup2(wr, 1_i32);
+            let _ = close(wr);
+
+            if let Ok(null) = OpenOptions::new().read(true).write(true).open("/dev/null") {
+                let _ = dup2(null.as_raw_fd(), 0_i32);
+                let _ = dup2(null.as_raw_fd(), 2_i32);
+            }
+
+            let mut smsh = Shell::new(false);
+
+            for func in functions {
+                smsh.insert_user_function(func.clone(), true);
+            }
+
+            for (key, val) in variables {
+                smsh.insert_user_variable(key.clone(), val.clone(), true);
+            }
+
+            let mut argv = vec![function.to_string()];
+            argv.extend(arguments);
+            let _ = smsh.run_source(func.build_source(argv));
+
+            std::process::exit(0);
+        }
+        Err(_) => {
+            let _ = close(rd);
+            let _ = close(wr);
+            vec![]
+        }
+    }
+}
+
+// Reads `fd` to its end, or until `deadline`
+fn read_until(fd: RawFd, deadline: Instant) -> Vec<u8> {
+    let mut output = Vec::<u8>::new();
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+
+        match poll(&mut fds, timeout.as_millis() as i32) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(Errno::EINTR) => continue,
+            Err(_) => break,
+        }
+
+        match read(fd, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => output.extend_from_slice(&buf[..n]),
+            Err(Errno::EINTR) => continue,
+            Err(_) => break,
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn path(words: &[&str]) -> Vec<String> {
+        words.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn completions_1() {
+        let mut completions = Completions::default();
+        completions.update(&path(&["git", "commit"]), |x| {
+            x.flags = vec!["--amend".to_string()]
+        });
+        completions.update(&path(&["git", "add"]), |x| {
+            x.argument = Some(Argument::File)
+        });
+
+        let position = completions
+            .position(&["/usr/bin/git", "--no-pager"])
+            .unwrap();
+        assert_eq!(vec!["commit", "add"], position.rule.subcommands);
+        assert!(!position.after_argument);
+
+        let position = completions.position(&["git", "commit", "-m", "x"]).unwrap();
+        assert_eq!(path(&["git", "commit"]), position.path);
+        assert!(position.after_argument);
+
+        assert!(completions.position(&["cat"]).is_none());
+
+        assert!(completions.remove("git"));
+        assert!(completions.position(&["git"]).is_none());
+    }
+
+    #[test]
+    fn function_words_1() {
+        let func = UserFunction::new("targets".to_string(), vec!["echo staging {1}".to_string()]);
+
+        let words = function_words("targets", vec!["production".to_string()], &[func], &[]);
+        assert_eq!(vec!["staging", "production"], words);
+    }
+
+    #[test]
+    fn function_words_2() {
+        // What was printed in time is kept
+        let body = vec!["echo early".to_string(), "sleep 10".to_string()];
+        let func = UserFunction::new("slow".to_string(), body);
+
+        let start = Instant::now();
+        let words = function_words("slow", vec![], &[func], &[]);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(vec!["early"], words);
+
+        // Nothing is read from the terminal
+        let func = UserFunction::new("reads".to_string(), vec!["cat".to_string()]);
+        assert!(function_words("reads", vec![], &[func], &[]).is_empty());
+    }
+}
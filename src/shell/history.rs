@@ -0,0 +1,401 @@
+// Interactive history, kept in `$XDG_DATA_HOME/smsh/history`.  Each entry
+// records when and where a line was run, how it exited and how long it
+// took.  The terminal records each line as it is entered, and the shell
+// completes the entry before its next prompt.  See doc/history.md.
+
+use anyhow::Result;
+use xdg::BaseDirectories;
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_HISTORY_SIZE: usize = 10000;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HistoryEntry {
+    pub timestamp: u64, // Seconds since the epoch
+    pub cwd: String,
+    pub status: Option<i32>,        // Unknown until the line has run
+    pub duration: Option<Duration>, // Likewise
+    pub command: String,
+}
+
+pub struct History {
+    path: Option<PathBuf>, // None keeps history in memory alone
+    entries: Vec<HistoryEntry>,
+    limit: usize,
+    pending: Option<(usize, Instant)>, // The entry being run, and since when
+    replaced: bool,                    // Whether the pending entry replaced the last
+    saved: usize,                      // Entries in the file, as far as we know
+    generation: u64,                   // Changes whenever entries are removed
+}
+
+// Shared by the shell and its terminal
+#[derive(Clone)]
+pub struct SharedHistory(Arc<Mutex<History>>);
+
+impl SharedHistory {
+    pub fn new(history: History) -> Self {
+        SharedHistory(Arc::new(Mutex::new(history)))
+    }
+
+    // A panic while holding the lock leaves the history usable
+    pub fn lock(&self) -> MutexGuard<'_, History> {
+        match self.0.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl History {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        History {
+            path,
+            entries: vec![],
+            limit: DEFAULT_HISTORY_SIZE,
+            pending: None,
+            replaced: false,
+            saved: 0,
+            generation: 0,
+        }
+    }
+
+    // We do not want this function to fail; unreadable entries are skipped
+    pub fn open() -> Self {
+        let path = BaseDirectories::with_prefix("smsh")
+            .ok()
+            .map(|x| x.get_data_home().join("history"));
+
+        let mut history = History::new(path);
+
+        if let Some(path) = &history.path {
+            if let Ok(text) = fs::read_to_string(path) {
+                for entry in text.lines().filter_map(decode_entry) {
+                    history.push(entry);
+                    history.saved += 1;
+                }
+            }
+        }
+
+        history
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Records `command` as about to run in the current directory.  Lines
+    // that are empty or begin with a space are not recorded, and a line
+    // repeating the last one updates it instead.  Returns whether the
+    // line was recorded.
+    pub fn start(&mut self, command: &str) -> bool {
+        if command.trim().is_empty() || command.starts_with(' ') {
+            return false;
+        }
+
+        let entry = HistoryEntry {
+            timestamp: now(),
+            cwd: current_dir(),
+            status: None,
+            duration: None,
+            command: command.to_string(),
+        };
+
+        self.replaced = self.push(entry);
+        self.pending = Some((self.entries.len() - 1, Instant::now()));
+
+        true
+    }
+
+    // An entry repeating the last one replaces it.  Returns whether it did.
+    fn push(&mut self, entry: HistoryEntry) -> bool {
+        match self.entries.last_mut() {
+            Some(last) if last.command == entry.command => {
+                *last = entry;
+                true
+            }
+            _ => {
+                self.entries.push(entry);
+                false
+            }
+        }
+    }
+
+    // Completes the entry being run, if any, and saves it
+    pub fn finish(&mut self, status: i32) -> Result<()> {
+        let (index, started) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        if let Some(entry) = self.entries.get_mut(index) {
+            entry.status = Some(status);
+            entry.duration = Some(started.elapsed());
+        }
+
+        let index = index.checked_sub(self.trim());
+
+        // An entry that replaced the last is not added to the file, as
+        // the file's own last line is that same command
+        if !self.replaced {
+            if let Some(entry) = index.and_then(|x| self.entries.get(x)).cloned() {
+                self.append(&entry)?;
+            }
+        }
+
+        // The file is let grow a quarter past the limit before it is
+        // rewritten, rather than rewritten after every line
+        if self.saved > self.limit + self.limit / 4 {
+            self.rewrite()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        self.entries.clear();
+        self.pending = None;
+        self.generation += 1;
+        self.rewrite()
+    }
+
+    // Returns the number of entries removed
+    fn trim(&mut self) -> usize {
+        let excess = self.entries.len().saturating_sub(self.limit);
+
+        if excess > 0 {
+            self.entries.drain(..excess);
+            self.pending = None;
+            self.generation += 1;
+        }
+
+        excess
+    }
+
+    fn append(&mut self, entry: &HistoryEntry) -> Result<()> {
+        if let Some(path) = &self.path {
+            create_parent(path)?;
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", encode_entry(entry))?;
+            self.saved += 1;
+        }
+
+        Ok(())
+    }
+
+    fn rewrite(&mut self) -> Result<()> {
+        if let Some(path) = &self.path {
+            let mut text = String::new();
+
+            for entry in &self.entries {
+                text.push_str(&encode_entry(entry));
+                text.push('\n');
+            }
+
+            create_parent(path)?;
+            fs::write(path, text)?;
+            self.saved = self.entries.len();
+        }
+
+        Ok(())
+    }
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+fn current_dir() -> String {
+    env::current_dir()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+// One entry per line:  timestamp, duration in milliseconds, exit
+// status, cwd and command, separated by tabs.  Unknown fields are empty.
+fn encode_entry(entry: &HistoryEntry) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}",
+        entry.timestamp,
+        entry
+            .duration
+            .map(|x| x.as_millis().to_string())
+            .unwrap_or_default(),
+        entry.status.map(|x| x.to_string()).unwrap_or_default(),
+        escape(&entry.cwd),
+        escape(&entry.command)
+    )
+}
+
+fn decode_entry(line: &str) -> Option<HistoryEntry> {
+    let fields: Vec<&str> = line.splitn(5, '\t').collect();
+
+    if fields.len() != 5 {
+        return None;
+    }
+
+    Some(HistoryEntry {
+        timestamp: fields[0].parse().ok()?,
+        duration: fields[1].parse().ok().map(Duration::from_millis),
+        status: fields[2].parse().ok(),
+        cwd: unescape(fields[3]),
+        command: unescape(fields[4]),
+    })
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_entry_1() {
+        let entry = HistoryEntry {
+            timestamp: 1700000000,
+            cwd: "/tmp/a\tb".to_string(),
+            status: Some(1),
+            duration: Some(Duration::from_millis(1500)),
+            command: "echo 'one\ntwo' \\".to_string(),
+        };
+
+        let line = encode_entry(&entry);
+        assert!(!line.contains('\n'));
+        assert_eq!(Some(entry), decode_entry(&line));
+
+        assert!(decode_entry("garbage").is_none());
+    }
+
+    #[test]
+    fn history_1() {
+        let mut history = History::new(None);
+
+        assert!(history.start("ls"));
+        history.finish(0).unwrap();
+        assert!(!history.start(" secret"));
+        assert!(!history.start(""));
+        assert!(history.start("false"));
+        history.finish(1).unwrap();
+
+        // Repeating a line updates its entry
+        assert!(history.start("false"));
+        history.finish(2).unwrap();
+
+        let statuses: Vec<Option<i32>> = history.entries().iter().map(|x| x.status).collect();
+        assert_eq!(vec![Some(0), Some(2)], statuses);
+    }
+
+    #[test]
+    fn history_2() {
+        let mut history = History::new(None);
+        history.set_limit(2);
+
+        for command in ["one", "two", "three"] {
+            history.start(command);
+            history.finish(0).unwrap();
+        }
+
+        let commands: Vec<&str> = history
+            .entries()
+            .iter()
+            .map(|x| x.command.as_str())
+            .collect();
+        assert_eq!(vec!["two", "three"], commands);
+        assert_eq!(1, history.generation());
+
+        history.clear().unwrap();
+        assert!(history.entries().is_empty());
+    }
+
+    #[test]
+    fn history_3() {
+        let path = env::temp_dir().join(format!("smsh-history-3-{}", std::process::id()));
+        let saved = || fs::read_to_string(&path).unwrap().lines().count();
+
+        let mut history = History::new(Some(path.clone()));
+        history.set_limit(4);
+
+        // A repeated line is not saved again
+        for command in ["one", "two", "two", "three"] {
+            history.start(command);
+            history.finish(0).unwrap();
+        }
+        assert_eq!(3, saved());
+
+        // The file is rewritten only once a quarter past the limit
+        for command in ["four", "five"] {
+            history.start(command);
+            history.finish(0).unwrap();
+        }
+        assert_eq!(4, history.entries().len());
+        assert_eq!(5, saved());
+
+        history.start("six");
+        history.finish(0).unwrap();
+        assert_eq!(4, saved());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -12,7 +12,7 @@ pub fn push_interactive_init_script(smsh: &mut Shell) {
             let temp = PathBuf::from("smsh/init");
 
             if let Some(path) = base_dirs.find_config_file(temp) {
-                match Script::build_source(path) {
+                match Script::build_source(path, None) {
                     Ok(script) => {
                         smsh.push_source(script);
                     }
@@ -0,0 +1,527 @@
+// Each pipeline that forks is tracked as a job.  When the shell has
+// control of a terminal, a job's processes share a process group, and
+// the foreground job owns the terminal while it runs.
+
+use anyhow::{anyhow, Result};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::termios::{tcgetattr, tcsetattr, SetArg, Termios};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{getpgrp, getpid, isatty, setpgid, tcsetpgrp, Pid};
+
+use std::fmt;
+
+use super::signals::exit_status;
+
+// File descriptor of the controlling terminal
+const TERMINAL: i32 = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ProcessState {
+    Running,
+    Stopped,
+    Exited(i32), // Exit status, or 128 + signal number
+}
+
+#[derive(Clone, Debug)]
+struct Process {
+    pid: Pid,
+    state: ProcessState,
+}
+
+#[derive(Clone, Debug)]
+pub struct Job {
+    id: usize, // Zero until the job enters the job table
+    pgid: Option<Pid>,
+    processes: Vec<Process>,
+    command: String,
+    reported: JobState, // Last state the user was told of
+    tmodes: Option<Termios>,
+}
+
+impl Job {
+    pub fn new(command: String) -> Self {
+        Job {
+            id: 0,
+            pgid: None,
+            processes: vec![],
+            command,
+            reported: JobState::Running,
+            tmodes: None,
+        }
+    }
+
+    // Called in the parent after forking `pid`.  Both parent and child
+    // set the process group, so that neither depends on the other winning the race.
+    pub fn push(&mut self, pid: Pid, job_control: bool) {
+        if job_control {
+            let pgid = *self.pgid.get_or_insert(pid);
+            let _ = setpgid(pid, pgid);
+        }
+
+        self.processes.push(Process {
+            pid,
+            state: ProcessState::Running,
+        });
+    }
+
+    pub fn pgid(&self) -> Option<Pid> {
+        self.pgid
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty()
+    }
+
+    // Pid of the last process in the pipeline
+    pub fn last_pid(&self) -> Option<Pid> {
+        self.processes.last().map(|x| x.pid)
+    }
+
+    pub fn contains(&self, pid: Pid) -> bool {
+        self.processes.iter().any(|x| x.pid == pid)
+    }
+
+    pub fn state(&self) -> JobState {
+        if self
+            .processes
+            .iter()
+            .all(|x| matches!(x.state, ProcessState::Exited(_)))
+        {
+            JobState::Done
+        } else if self
+            .processes
+            .iter()
+            .any(|x| x.state == ProcessState::Running)
+        {
+            JobState::Running
+        } else {
+            JobState::Stopped
+        }
+    }
+
+    // The return value of a job is that of its last process
+    pub fn rv(&self) -> i32 {
+        match self.processes.last().map(|x| x.state) {
+            Some(ProcessState::Exited(rv)) => rv,
+            _ => 0,
+        }
+    }
+
+    fn update(&mut self, status: WaitStatus) {
+        let pid = match status.pid() {
+            Some(pid) => pid,
+            None => return,
+        };
+
+        let state = match status {
+            WaitStatus::Stopped(..) => ProcessState::Stopped,
+            WaitStatus::Continued(_) => ProcessState::Running,
+            _ => match exit_status(status) {
+                Some(rv) => ProcessState::Exited(rv),
+                None => return,
+            },
+        };
+
+        for process in &mut self.processes {
+            if process.pid == pid {
+                process.state = state;
+            }
+        }
+    }
+
+    // Blocks until every process has exited, or until the job stops
+    pub fn wait(&mut self) -> Result<()> {
+        while self.state() == JobState::Running {
+            let pid = self
+                .processes
+                .iter()
+                .find(|x| x.state == ProcessState::Running)
+                .map(|x| x.pid)
+                .unwrap();
+
+            match waitpid(pid, Some(WaitPidFlag::WUNTRACED)) {
+                Ok(status) => self.update(status),
+                Err(e) => return Err(anyhow!("wait: {}", e.desc())),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Collects state changes without blocking
+    fn poll(&mut self) {
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+
+        for i in 0..self.processes.len() {
+            if matches!(self.processes[i].state, ProcessState::Exited(_)) {
+                continue;
+            }
+
+            if let Ok(status) = waitpid(self.processes[i].pid, Some(flags)) {
+                self.update(status);
+            }
+        }
+    }
+
+    pub fn signal(&self, signal: Signal) -> Result<()> {
+        if let Some(pgid) = self.pgid {
+            kill(Pid::from_raw(-pgid.as_raw()), signal)?;
+        } else {
+            for process in &self.processes {
+                if !matches!(process.state, ProcessState::Exited(_)) {
+                    kill(process.pid, signal)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Resumes a stopped job
+    // The job is taken to be running only once it has been sent SIGCONT
+    pub fn resume(&mut self) -> Result<()> {
+        self.signal(Signal::SIGCONT)?;
+
+        for process in &mut self.processes {
+            if process.state == ProcessState::Stopped {
+                process.state = ProcessState::Running;
+            }
+        }
+
+        self.reported = JobState::Running;
+        Ok(())
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    // As listed by `jobs`, with `marker` following the job number
+    pub fn status_line(&self, marker: char) -> String {
+        let state = match self.state() {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped => "Stopped".to_string(),
+            JobState::Done => match self.rv() {
+                0 => "Done".to_string(),
+                rv => format!("Exit {}", rv),
+            },
+        };
+
+        format!("[{}]{} {:<10}{}", self.id, marker, state, self.command)
+    }
+}
+
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.status_line(' '))
+    }
+}
+
+pub struct Jobs {
+    jobs: Vec<Job>,          // Most recent last
+    shell_pgid: Option<Pid>, // Some if the shell has job control
+    shell_tmodes: Option<Termios>,
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Jobs {
+            jobs: vec![],
+            shell_pgid: None,
+            shell_tmodes: None,
+        }
+    }
+
+    // Places the shell in its own process group and takes the terminal.
+    // Does nothing unless stdin is a terminal.  Terminal signals must
+    // already be ignored.
+    pub fn enable_job_control(&mut self) {
+        if !isatty(TERMINAL).unwrap_or(false) {
+            return;
+        }
+
+        let pgid = getpid();
+        if getpgrp() != pgid && setpgid(pgid, pgid).is_err() {
+            return;
+        }
+
+        if tcsetpgrp(TERMINAL, pgid).is_ok() {
+            self.shell_pgid = Some(pgid);
+            self.shell_tmodes = tcgetattr(TERMINAL).ok();
+        }
+    }
+
+    pub fn has_job_control(&self) -> bool {
+        self.shell_pgid.is_some()
+    }
+
+    // Adds `job` to the table, giving it the lowest free job number
+    pub fn insert(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = (1..)
+                .find(|n| !self.jobs.iter().any(|x| x.id == *n))
+                .unwrap();
+        }
+
+        let id = job.id;
+        self.jobs.push(job);
+        id
+    }
+
+    // Accepts `%n`, `%+`, `%%`, `%-` and `%prefix`; with no spec, the current job
+    pub fn remove(&mut self, spec: Option<&str>) -> Result<Job> {
+        let idx = self.find(spec)?;
+        Ok(self.jobs.remove(idx))
+    }
+
+    pub fn get(&mut self, spec: Option<&str>) -> Result<&mut Job> {
+        let idx = self.find(spec)?;
+        Ok(&mut self.jobs[idx])
+    }
+
+    fn find(&self, spec: Option<&str>) -> Result<usize> {
+        let spec = spec.unwrap_or("%+");
+
+        let idx = match spec.strip_prefix('%') {
+            Some("+") | Some("%") | Some("") => self.jobs.len().checked_sub(1),
+            Some("-") => self.jobs.len().checked_sub(2),
+            Some(n) if n.chars().all(|c| c.is_ascii_digit()) => {
+                let n: usize = n.parse()?;
+                self.jobs.iter().position(|x| x.id == n)
+            }
+            Some(prefix) => self.jobs.iter().position(|x| x.command.starts_with(prefix)),
+            None => match spec.parse::<i32>() {
+                Ok(pid) => self
+                    .jobs
+                    .iter()
+                    .position(|x| x.contains(Pid::from_raw(pid))),
+                Err(_) => return Err(anyhow!("{}: Not a job specification", spec)),
+            },
+        };
+
+        idx.ok_or_else(|| anyhow!("{}: No such job", spec))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    // Marker used by `jobs`: `+` for the current job, `-` for the previous
+    pub fn marker(&self, job: &Job) -> char {
+        let n = self.jobs.len();
+
+        if n > 0 && self.jobs[n - 1].id == job.id {
+            '+'
+        } else if n > 1 && self.jobs[n - 2].id == job.id {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    // Collects state changes of every job without blocking
+    pub fn poll(&mut self) {
+        for job in &mut self.jobs {
+            job.poll();
+        }
+    }
+
+    // Returns a line for each job that finished or stopped since it was
+    // last reported, and drops finished jobs from the table
+    pub fn notifications(&mut self) -> Vec<String> {
+        self.poll();
+
+        let mut notices = vec![];
+
+        for job in &mut self.jobs {
+            let state = job.state();
+
+            if state != job.reported {
+                job.reported = state;
+
+                if state != JobState::Running {
+                    notices.push(job.to_string());
+                }
+            }
+        }
+
+        self.jobs.retain(|x| x.state() != JobState::Done);
+
+        notices
+    }
+
+    // Runs `job` in the foreground, handing it the terminal if the shell
+    // has job control.  A job that stops is placed in the table.
+    // Returns the job's return value.
+    pub fn foreground(&mut self, job: Job) -> Result<i32> {
+        let shell_pgid = self.give_terminal(&job);
+        self.wait_in_foreground(job, shell_pgid)
+    }
+
+    // Continues the job given by `spec` in the foreground.  The job is
+    // handed the terminal before it is sent SIGCONT, and is left in the
+    // table should that fail.
+    pub fn resume_in_foreground(&mut self, spec: Option<&str>) -> Result<i32> {
+        let idx = self.find(spec)?;
+        let shell_pgid = self.give_terminal(&self.jobs[idx]);
+
+        if let Err(e) = self.jobs[idx].resume() {
+            self.take_terminal(shell_pgid);
+            return Err(e);
+        }
+
+        let job = self.jobs.remove(idx);
+        self.wait_in_foreground(job, shell_pgid)
+    }
+
+    // Hands the terminal to `job`, in the modes it last had, if the shell
+    // has job control.  Yields the shell's process group, to take the
+    // terminal back with.
+    fn give_terminal(&self, job: &Job) -> Option<Pid> {
+        match (self.shell_pgid, job.pgid) {
+            (Some(shell_pgid), Some(pgid)) => {
+                if let Some(tmodes) = &job.tmodes {
+                    let _ = tcsetattr(TERMINAL, SetArg::TCSADRAIN, tmodes);
+                }
+                let _ = tcsetpgrp(TERMINAL, pgid);
+                Some(shell_pgid)
+            }
+            _ => None,
+        }
+    }
+
+    fn take_terminal(&self, shell_pgid: Option<Pid>) {
+        if let Some(shell_pgid) = shell_pgid {
+            let _ = tcsetpgrp(TERMINAL, shell_pgid);
+            if let Some(tmodes) = &self.shell_tmodes {
+                let _ = tcsetattr(TERMINAL, SetArg::TCSADRAIN, tmodes);
+            }
+        }
+    }
+
+    fn wait_in_foreground(&mut self, mut job: Job, shell_pgid: Option<Pid>) -> Result<i32> {
+        let res = job.wait();
+
+        // A stopped job keeps its terminal modes for when it resumes
+        if shell_pgid.is_some() {
+            job.tmodes = tcgetattr(TERMINAL).ok();
+        }
+        self.take_terminal(shell_pgid);
+
+        res?;
+
+        if job.state() == JobState::Stopped {
+            job.reported = JobState::Stopped;
+            eprintln!();
+
+            let id = self.insert(job);
+            let job = self.get(Some(&format!("%{}", id)))?;
+            eprintln!("{}", job);
+
+            Ok(128 + Signal::SIGTSTP as i32)
+        } else {
+            Ok(job.rv())
+        }
+    }
+}
+
+// Called in a forked child before it executes anything, and before it
+// restores SIGTTOU's default disposition.  Mirrors the parent's call to
+// setpgid in Job::push.
+pub fn enter_process_group(pgid: Option<Pid>, foreground: bool) {
+    let _ = setpgid(Pid::from_raw(0), pgid.unwrap_or_else(|| Pid::from_raw(0)));
+
+    if foreground {
+        let _ = tcsetpgrp(TERMINAL, getpgrp());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn job(command: &str, states: &[ProcessState]) -> Job {
+        let mut job = Job::new(command.to_string());
+        for (i, state) in states.iter().enumerate() {
+            job.processes.push(Process {
+                pid: Pid::from_raw(i as i32 + 100),
+                state: *state,
+            });
+        }
+        job
+    }
+
+    #[test]
+    fn job_state_1() {
+        let job = job(
+            "sleep 1 | cat",
+            &[ProcessState::Exited(0), ProcessState::Running],
+        );
+        assert_eq!(JobState::Running, job.state());
+    }
+
+    #[test]
+    fn job_state_2() {
+        let job = job(
+            "sleep 1 | cat",
+            &[ProcessState::Stopped, ProcessState::Exited(0)],
+        );
+        assert_eq!(JobState::Stopped, job.state());
+    }
+
+    #[test]
+    fn job_state_3() {
+        let job = job("false", &[ProcessState::Exited(1)]);
+        assert_eq!(JobState::Done, job.state());
+        assert_eq!(1, job.rv());
+    }
+
+    #[test]
+    fn find_job_1() {
+        let mut jobs = Jobs::new();
+        jobs.insert(job("sleep 10", &[ProcessState::Running]));
+        jobs.insert(job("vim file", &[ProcessState::Stopped]));
+
+        assert_eq!(2, jobs.get(None).unwrap().id());
+        assert_eq!(1, jobs.get(Some("%-")).unwrap().id());
+        assert_eq!(1, jobs.get(Some("%1")).unwrap().id());
+        assert_eq!(2, jobs.get(Some("%vim")).unwrap().id());
+        assert!(jobs.get(Some("%3")).is_err());
+        assert!(jobs.get(Some("3")).is_err());
+        assert_eq!(1, jobs.get(Some("100")).unwrap().id());
+        assert!(jobs.get(Some("x")).is_err());
+    }
+
+    #[test]
+    fn insert_job_1() {
+        let mut jobs = Jobs::new();
+        jobs.insert(job("a", &[ProcessState::Running]));
+        jobs.insert(job("b", &[ProcessState::Running]));
+        jobs.remove(Some("%1")).unwrap();
+
+        assert_eq!(1, jobs.insert(job("c", &[ProcessState::Running])));
+    }
+
+    #[test]
+    fn resume_in_foreground_1() {
+        let mut jobs = Jobs::new();
+        let mut stopped = job("vim file", &[ProcessState::Stopped]);
+        stopped.pgid = Some(Pid::from_raw(i32::MAX)); // No such process group
+        jobs.insert(stopped);
+
+        // A job that cannot be continued stays in the table, stopped
+        assert!(jobs.resume_in_foreground(None).is_err());
+        assert_eq!(JobState::Stopped, jobs.get(Some("%1")).unwrap().state());
+    }
+}
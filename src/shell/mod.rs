@@ -1,50 +1,92 @@
 use crate::line::Line;
-use crate::sources::{tty::Tty, user_function::UserFunction, Source, SourceKind, Sources};
+use crate::sources::{
+    tty::Tty, user_function::UserFunction, Interrupt, Source, SourceKind, Sources,
+};
 use anyhow::{anyhow, Result};
-use nix::sys::wait::{wait, WaitStatus};
-use nix::unistd::{self, fork, ForkResult};
+use nix::errno::Errno;
+use nix::unistd;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::CString;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
 mod state;
 use state::State;
 pub mod modules;
-use modules::{load_module, Builtin, Module};
+use modules::{load_module, Builtin, LoadedModule, Module, Plugin};
 mod init;
 use init::push_interactive_init_script;
+pub mod jobs;
+use jobs::Jobs;
+mod signals;
+use signals::{ignore_terminal_signals, restore_default_signals};
+mod scope;
+use scope::Scopes;
+mod prompt;
+pub mod variables;
+pub use prompt::default_prompt;
+use prompt::render_prompt;
+mod commands;
+pub use commands::path_commands;
+use commands::suggest;
+pub use commands::Commands;
+pub mod history;
+use history::{History, SharedHistory, DEFAULT_HISTORY_SIZE};
+pub mod names;
+use names::SharedNames;
+pub mod completion;
+use completion::Completions;
 
 pub struct Shell {
     state: State,
     sources: Sources,
-    builtins: HashMap<&'static str, Builtin>,
-    user_variables: HashMap<String, String>,
-    user_functions: HashMap<String, UserFunction>,
+    builtins: HashMap<String, Builtin>,
+    modules: BTreeMap<String, LoadedModule>,
+    plugins: HashMap<String, Plugin>, // Loaded native modules
+    scopes: Scopes,                   // User variables and functions
+    commands: Commands,
+    history: SharedHistory,
+    names: SharedNames, // For the terminal; see names.rs
+    completions: Completions,
+    jobs: Jobs,
 }
 
 impl Shell {
     // This function should never fail, so that
     // a user of smsh always gets into its main loop.
-    pub fn new() -> Shell {
-        let state = State::new();
+    // Only an interactive shell reads from the terminal.
+    pub fn new(interactive: bool) -> Shell {
+        let state = State::new(interactive);
         let sources = Sources::new();
-        let builtins = HashMap::<&'static str, Builtin>::new();
-        let user_variables = HashMap::<String, String>::new();
-        let user_functions = HashMap::<String, UserFunction>::new();
+        let builtins = HashMap::<String, Builtin>::new();
         let mut smsh = Shell {
             state,
             sources,
             builtins,
-            user_variables,
-            user_functions,
+            modules: BTreeMap::new(),
+            plugins: HashMap::new(),
+            scopes: Scopes::new(),
+            commands: Commands::new(),
+            history: SharedHistory::new(History::new(None)), // Kept only when interactive
+            names: SharedNames::default(),
+            completions: Completions::default(),
+            jobs: Jobs::new(),
         };
 
         load_module(&mut smsh, Module::Core);
+        load_module(&mut smsh, Module::Job);
+        load_module(&mut smsh, Module::History);
+        load_module(&mut smsh, Module::Completion);
 
         // TODO: Add 'queue_source'
         if smsh.is_interactive() {
-            smsh.push_source(Tty::new());
+            smsh.history = SharedHistory::new(History::open());
+            smsh.push_source(Tty::new(smsh.history.clone(), smsh.names.clone()));
+            ignore_terminal_signals();
+            smsh.jobs.enable_job_control();
             push_interactive_init_script(&mut smsh);
         }
 
@@ -53,24 +95,177 @@ impl Shell {
 
     pub fn run(&mut self) -> Result<()> {
         while let Some(mut line) = self.get_line()? {
-            line.expand(self)?;
-            line.select()?;
             line.execute(self)?;
         }
 
         Ok(())
     }
 
-    // TODO: Overhaul source constructs s.t. this fn deals with sources
+    // Runs `source` to exhaustion before returning.  Lines beneath
+    // `source` are not visible to it.  On error, what is left of it
+    // is discarded, along with its scopes.
+    pub fn run_source(&mut self, source: Box<dyn Source>) -> Result<()> {
+        self.sources.push_floor();
+        self.push_source(source);
+
+        let res = self.run();
+
+        if res.is_err() {
+            for _ in 0..self.sources.discard() {
+                self.scopes.pop();
+            }
+        }
+
+        self.sources.pop_floor();
+
+        res
+    }
+
+    // Exhausted sources are discarded
     pub fn get_line(&mut self) -> Result<Option<Line>> {
-        self.sources.get_line()
+        while let Some(source) = self.sources.top() {
+            if source.will_prompt() {
+                self.before_prompt();
+            }
+
+            // A loop tests its condition before each pass, and is done
+            // once it is false.  Its return value is that of its last pass.
+            let done = match self.sources.top().unwrap().condition() {
+                Some(condition) => {
+                    let rv = self.rv();
+                    let source_kind = self.sources.top().unwrap().get_source_kind();
+                    let done = !self.evaluate_conditional(&condition, &source_kind)?;
+                    if done {
+                        self.set_rv(rv);
+                    }
+                    done
+                }
+                None => false,
+            };
+
+            if !done {
+                if let Some(line) = self.sources.top().unwrap().get_line()? {
+                    return Ok(Some(line));
+                }
+            }
+
+            if let Some(source) = self.sources.pop() {
+                if source.arguments().is_some() {
+                    self.scopes.pop();
+                }
+            }
+        }
+
+        Ok(None)
     }
 
+    // Captures one block of lines of the given source kind
     pub fn get_block(&mut self, source_kind: &SourceKind, indent: usize) -> Result<Vec<Line>> {
-        self.sources.get_block(source_kind, indent)
+        let mut lines = Vec::<Line>::new();
+
+        while let Some(line) = self.get_line()? {
+            if *line.source() == *source_kind && line.indentation() >= indent {
+                lines.push(line);
+            } else {
+                self.push_line(line);
+                break;
+            }
+        }
+
+        Ok(lines)
     }
 
+    // Captures the body of a here-document: the lines of the given source
+    // kind indented at least `indent`, and any blank lines among them.
+    // Blank lines at the end of what the user entered end the body,
+    // rather than have the user prompted for more.
+    pub fn get_here_doc_body(
+        &mut self,
+        source_kind: &SourceKind,
+        indent: usize,
+    ) -> Result<Vec<Line>> {
+        let mut lines = Vec::<Line>::new();
+        let mut held = Vec::<Line>::new(); // Kept only if the body goes on
+
+        loop {
+            if !held.is_empty() && self.sources.top().is_some_and(|x| x.will_prompt()) {
+                break;
+            }
+
+            let line = match self.get_line()? {
+                Some(line) => line,
+                None => break,
+            };
+
+            if *line.source() != *source_kind {
+                held.push(line);
+                break;
+            } else if line.raw_text().trim().is_empty() {
+                held.push(line);
+            } else if line.indentation() >= indent {
+                lines.append(&mut held);
+                lines.push(line);
+            } else {
+                held.push(line);
+                break;
+            }
+        }
+
+        if !held.is_empty() {
+            self.push_block(held);
+        }
+
+        Ok(lines)
+    }
+
+    // Called each time the user is about to be prompted
+    fn before_prompt(&mut self) {
+        for notice in self.jobs.notifications() {
+            eprintln!("{}", notice);
+        }
+
+        self.finish_history();
+        self.names.lock().update(self);
+
+        let prompt = render_prompt(self);
+
+        if let Some(source) = self.sources.top() {
+            source.set_prompt(prompt);
+        }
+    }
+
+    pub fn history(&self) -> &SharedHistory {
+        &self.history
+    }
+
+    pub fn completions(&mut self) -> &mut Completions {
+        &mut self.completions
+    }
+
+    // Completes the history entry of the line last entered at the
+    // terminal, once it has run.  The variable `history_size` limits
+    // the number of entries kept.
+    pub fn finish_history(&mut self) {
+        let limit = self
+            .get_user_variable("history_size")
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(DEFAULT_HISTORY_SIZE);
+
+        let mut history = self.history.lock();
+        history.set_limit(limit);
+
+        if let Err(e) = history.finish(self.state.rv) {
+            eprintln!("smsh: history: {}", e);
+        }
+    }
+
+    // A source with a scope of its own, such as a user function,
+    // keeps it until the source is exhausted
     pub fn push_source(&mut self, source: Box<dyn Source>) {
+        if let Some(arguments) = source.arguments() {
+            self.scopes.push(arguments);
+        }
+
         self.sources.push_source(source)
     }
 
@@ -82,46 +277,93 @@ impl Shell {
         self.sources.push_block(lines);
     }
 
+    // Scopes are kept, so that a forked child sees the
+    // variables and functions of its parent
     pub fn clear_sources(&mut self) {
         self.sources.clear();
     }
 
+    // Returns false if there is no loop to interrupt
+    pub fn interrupt_loop(&mut self, interrupt: Interrupt) -> bool {
+        self.sources.interrupt(interrupt)
+    }
+
     pub fn backtrace(&mut self) {
-        self.sources.backtrace()
+        for _ in 0..self.sources.backtrace() {
+            self.scopes.pop();
+        }
     }
 
-    fn is_interactive(&mut self) -> bool {
+    pub fn is_interactive(&self) -> bool {
         self.state.is_interactive()
     }
 
-    pub fn insert_user_variable(&mut self, key: String, val: String) {
-        self.user_variables.insert(key, val);
+    // A global variable or function is inserted into the root scope,
+    // otherwise into the current one
+    pub fn insert_user_variable(&mut self, key: String, val: String, global: bool) {
+        self.scopes.insert_variable(key, val, global);
+    }
+
+    // Positional arguments of the current scope
+    pub fn arguments(&self) -> &[String] {
+        self.scopes.arguments()
     }
 
     pub fn get_user_variable(&mut self, key: &str) -> Option<String> {
-        self.user_variables.get(key).cloned()
+        self.scopes.get_variable(key).cloned()
+    }
+
+    pub fn insert_user_function(&mut self, func: UserFunction, global: bool) {
+        self.scopes.insert_function(func, global);
+    }
+
+    pub fn remove_user_function(&mut self, name: &str, global: bool) -> Option<UserFunction> {
+        self.scopes.remove_function(name, global)
     }
 
-    pub fn insert_user_function(&mut self, func: UserFunction) {
-        self.user_functions.insert(func.name().to_string(), func);
+    pub fn jobs(&mut self) -> &mut Jobs {
+        &mut self.jobs
     }
 
     pub fn get_builtin(&self, command: &str) -> Option<&Builtin> {
         self.builtins.get(command)
     }
 
+    pub fn is_module_loaded(&self, name: &str) -> bool {
+        self.modules.contains_key(name)
+    }
+
     pub fn get_user_function(&self, name: &str) -> Option<UserFunction> {
-        if let Some(func) = self.user_functions.get(name) {
-            Some(func.clone())
-        } else {
-            None
-        }
+        self.scopes.get_function(name).cloned()
+    }
+
+    // Finds an external command on PATH
+    pub fn resolve_command(&mut self, name: &str) -> Option<PathBuf> {
+        self.commands.resolve(name)
+    }
+
+    // Suggests a function, builtin or external command for
+    // a command that was not found
+    pub fn suggest_command(&mut self, name: &str) -> Option<String> {
+        let external = self.commands.names();
+
+        let candidates = self
+            .scopes
+            .function_names()
+            .chain(self.builtins.keys().map(|x| x.as_str()))
+            .chain(external.iter().map(|x| x.as_str()));
+
+        suggest(name, candidates).map(|x| x.to_string())
     }
 
     pub fn state(&self) -> &State {
         &self.state
     }
 
+    pub fn set_last_background_pid(&mut self, pid: unistd::Pid) {
+        self.state.set_last_background_pid(pid);
+    }
+
     pub fn set_rv(&mut self, rv: i32) {
         self.state.rv = rv;
     }
@@ -130,36 +372,35 @@ impl Shell {
         self.state.rv
     }
 
-    // Executes `line` in a subshell environment, waits for it
-    // and collects its return value.
-    pub fn evaluate_conditional(&mut self, line: &str) -> Result<Option<bool>> {
-        match unsafe { fork()? } {
-            ForkResult::Parent { child: _, .. } => match wait()? {
-                WaitStatus::Exited(_pid, exit_status) => {
-                    if exit_status == 0 {
-                        Ok(Some(true))
-                    } else {
-                        Ok(Some(false))
-                    }
-                }
-                _ => Err(anyhow!(
-                    "wait: Failed to wait on subshell with line {}",
-                    line
-                )),
-            },
-
-            ForkResult::Child => {
-                self.clear_sources();
-                let line = Line::new(line.to_string(), 0, SourceKind::Subshell)?;
-                self.push_line(line);
+    // Executes `text` within the shell itself, as the conditional of
+    // `if`, `elif` or `while`, so that builtins and `let` act on the
+    // shell.  Only external commands and pipelines are forked.  A
+    // conditional that fails to run is false.  Its commands are looked
+    // up as those of a line of `source_kind` would be.
+    pub fn evaluate_conditional(&mut self, text: &str, source_kind: &SourceKind) -> Result<bool> {
+        let mut line = Line::new(text.to_string(), 0, source_kind.clone())?;
+
+        if !line.is_conditional() {
+            self.set_rv(2);
+            return Err(anyhow!("{}: Not a conditional", text));
+        }
 
-                Ok(None)
+        if let Err(e) = line.execute(self) {
+            eprintln!("smsh: {}", e);
+            if self.rv() == 0 {
+                self.set_rv(1);
             }
         }
+
+        Ok(self.rv() == 0)
     }
 
-    // WRONG!
-    pub fn execute_external_command(&mut self, args: Vec<&str>) -> Result<()> {
+    // Replaces the current process with the executable at `path`, given
+    // `args`.  Returns only on failure; meant to be called from within
+    // a forked child.
+    pub fn execute_external_command(&mut self, path: &Path, args: Vec<&str>) -> Errno {
+        restore_default_signals();
+
         let mut argv = Vec::<CString>::new();
 
         for arg in args {
@@ -167,14 +408,143 @@ impl Shell {
                 Ok(x) => x,
                 Err(e) => {
                     eprintln!("smsh (child): {}", e);
-                    exit(1);
+                    self.exit_child(1);
                 }
             };
 
             argv.push(c_arg);
         }
 
-        let _ = unistd::execvp(&argv[0], &argv);
-        Ok(())
+        let path = match CString::new(path.as_os_str().as_bytes()) {
+            Ok(x) => x,
+            Err(_) => return Errno::ENOENT,
+        };
+
+        unistd::execv(&path, &argv).unwrap_err()
+    }
+
+    // Gives a forked child the signal dispositions of a non-interactive
+    // shell.  Jobs, and job control, belong to the parent.
+    pub fn become_child(&mut self) {
+        restore_default_signals();
+        self.jobs = Jobs::new();
+    }
+
+    // Exits a forked child.  Output still buffered by the child
+    // would otherwise be lost.
+    pub fn exit_child(&mut self, rv: i32) -> ! {
+        let _ = io::stdout().flush();
+        exit(rv);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_lines(smsh: &mut Shell, lines: &[&str]) -> Result<()> {
+        let lines = lines
+            .iter()
+            .enumerate()
+            .map(|(i, x)| Line::new(x.to_string(), i + 1, SourceKind::Subshell).unwrap())
+            .collect();
+
+        smsh.push_block(lines);
+        smsh.run()
+    }
+
+    #[test]
+    fn run_source_1() {
+        let mut smsh = Shell::new(false);
+
+        // Nothing of a function is left to run once it fails
+        run_lines(
+            &mut smsh,
+            &[
+                "fn f:",
+                "    true >",
+                "    let --global after = 1",
+                "f or true",
+                "let --global caller = {0}",
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(None, smsh.get_user_variable("after"));
+        assert_eq!(Some("smsh".to_string()), smsh.get_user_variable("caller"));
+    }
+
+    #[test]
+    fn evaluate_conditional_1() {
+        let mut smsh = Shell::new(false);
+
+        // Acts on the shell itself
+        assert!(smsh
+            .evaluate_conditional("let x = 1", &SourceKind::Subshell)
+            .unwrap());
+        assert_eq!(Some("1".to_string()), smsh.get_user_variable("x"));
+
+        assert!(smsh
+            .evaluate_conditional("true and true", &SourceKind::Subshell)
+            .unwrap());
+        assert!(!smsh
+            .evaluate_conditional("true | false", &SourceKind::Subshell)
+            .unwrap());
+        assert!(!smsh
+            .evaluate_conditional("no-such-command-for-smsh", &SourceKind::Subshell)
+            .unwrap());
+        assert!(smsh
+            .evaluate_conditional("for x in a b", &SourceKind::Subshell)
+            .is_err());
+    }
+
+    #[test]
+    fn evaluate_conditional_2() {
+        let mut smsh = Shell::new(false);
+
+        // A function failing as a conditional is false, and nothing
+        // of it is left to run
+        run_lines(
+            &mut smsh,
+            &[
+                "fn f:",
+                "    true >",
+                "    let --global after = 1",
+                "if f",
+                "    let --global branch = if",
+                "else",
+                "    let --global branch = else",
+                "while f",
+                "    let --global looped = 1",
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(Some("else".to_string()), smsh.get_user_variable("branch"));
+        assert_eq!(None, smsh.get_user_variable("looped"));
+        assert_eq!(None, smsh.get_user_variable("after"));
+    }
+
+    #[test]
+    fn get_line_1() {
+        // A loop ending on its own at `while_limit` passes is not stopped
+        let lines = [
+            "let a = 0",
+            "let b = 0",
+            "while test {b} = 0",
+            "    if test {a} = 1",
+            "        let b = 1",
+            "    let a = 1",
+        ];
+
+        let mut smsh = Shell::new(false);
+        smsh.insert_user_variable("while_limit".to_string(), "2".to_string(), true);
+        run_lines(&mut smsh, &lines).unwrap();
+        assert_eq!(Some("1".to_string()), smsh.get_user_variable("b"));
+
+        let mut smsh = Shell::new(false);
+        smsh.insert_user_variable("while_limit".to_string(), "1".to_string(), true);
+        assert!(run_lines(&mut smsh, &lines).is_err());
+        assert_eq!(Some("0".to_string()), smsh.get_user_variable("b"));
     }
 }
@@ -0,0 +1,161 @@
+use crate::shell::completion::{Argument, Rule};
+use crate::shell::Shell;
+
+use anyhow::{anyhow, Result};
+
+// complete
+// complete command [subcommand ...] rule [word ...]
+// complete --remove command
+pub fn complete(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    if argv.len() == 1 {
+        for (path, rule) in smsh.completions().rules() {
+            for line in format_rule(path, rule) {
+                println!("{}", line);
+            }
+        }
+
+        smsh.set_rv(0);
+        return Ok(());
+    }
+
+    if argv[1] == "--remove" {
+        if argv.len() != 3 {
+            smsh.set_rv(2);
+            return Err(anyhow!("complete: Improper invocation of `complete`"));
+        }
+
+        return if smsh.completions().remove(argv[2]) {
+            smsh.set_rv(0);
+            Ok(())
+        } else {
+            smsh.set_rv(1);
+            Err(anyhow!("complete: {}: No completion rules", argv[2]))
+        };
+    }
+
+    // The command and its subcommands come before the rule
+    let i = argv
+        .iter()
+        .position(|x| x.starts_with('-'))
+        .unwrap_or(argv.len());
+    let path: Vec<String> = argv[1..i].iter().map(|x| x.to_string()).collect();
+
+    if path.is_empty() || i == argv.len() {
+        smsh.set_rv(2);
+        return Err(anyhow!("complete: Improper invocation of `complete`"));
+    }
+
+    let words: Vec<String> = argv[i + 1..].iter().map(|x| x.to_string()).collect();
+
+    let update: Box<dyn FnOnce(&mut Rule)> = match (argv[i], words.len()) {
+        ("--subcommands", _) => Box::new(|x| extend(&mut x.subcommands, words)),
+        ("--flags", _) => Box::new(|x| extend(&mut x.flags, words)),
+        ("--file", 0) => Box::new(|x| x.argument = Some(Argument::File)),
+        ("--directory", 0) => Box::new(|x| x.argument = Some(Argument::Directory)),
+        ("--words", _) => Box::new(|x| x.argument = Some(Argument::Words(words))),
+        ("--function", 1) => {
+            let function = words[0].clone();
+            Box::new(|x| x.argument = Some(Argument::Function(function)))
+        }
+        ("--file" | "--directory" | "--function", _) => {
+            smsh.set_rv(2);
+            return Err(anyhow!(
+                "complete: {}: Improper invocation of `complete`",
+                argv[i]
+            ));
+        }
+        (rule, _) => {
+            smsh.set_rv(2);
+            return Err(anyhow!("complete: {}: Unrecognized rule", rule));
+        }
+    };
+
+    smsh.completions().update(&path, update);
+
+    smsh.set_rv(0);
+    Ok(())
+}
+
+fn extend(list: &mut Vec<String>, words: Vec<String>) {
+    for word in words {
+        if !list.contains(&word) {
+            list.push(word);
+        }
+    }
+}
+
+// As the invocations of `complete` that would declare it
+fn format_rule(path: &[String], rule: &Rule) -> Vec<String> {
+    let path = path.join(" ");
+    let mut lines = Vec::<String>::new();
+
+    if !rule.subcommands.is_empty() {
+        lines.push(format!(
+            "complete {} --subcommands {}",
+            path,
+            rule.subcommands.join(" ")
+        ));
+    }
+
+    if !rule.flags.is_empty() {
+        lines.push(format!(
+            "complete {} --flags {}",
+            path,
+            rule.flags.join(" ")
+        ));
+    }
+
+    match &rule.argument {
+        Some(Argument::File) => lines.push(format!("complete {} --file", path)),
+        Some(Argument::Directory) => lines.push(format!("complete {} --directory", path)),
+        Some(Argument::Words(x)) => {
+            lines.push(format!("complete {} --words {}", path, x.join(" ")))
+        }
+        Some(Argument::Function(x)) => lines.push(format!("complete {} --function {}", path, x)),
+        None => {}
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn complete_1() {
+        let mut smsh = Shell::new(false);
+
+        complete(
+            &mut smsh,
+            vec!["complete", "tool", "deploy", "--words", "a", "b"],
+        )
+        .unwrap();
+        complete(
+            &mut smsh,
+            vec!["complete", "tool", "--flags", "-v", "--help"],
+        )
+        .unwrap();
+        assert!(complete(&mut smsh, vec!["complete", "tool"]).is_err());
+        assert!(complete(&mut smsh, vec!["complete", "tool", "--file", "x"]).is_err());
+        assert!(complete(&mut smsh, vec!["complete", "tool", "--bogus"]).is_err());
+
+        let lines: Vec<String> = smsh
+            .completions()
+            .rules()
+            .flat_map(|(path, rule)| format_rule(path, rule))
+            .collect();
+
+        assert_eq!(
+            vec![
+                "complete tool --subcommands deploy",
+                "complete tool --flags -v --help",
+                "complete tool deploy --words a b",
+            ],
+            lines
+        );
+
+        complete(&mut smsh, vec!["complete", "--remove", "tool"]).unwrap();
+        assert!(complete(&mut smsh, vec!["complete", "--remove", "tool"]).is_err());
+    }
+}
@@ -1,10 +1,9 @@
-use crate::shell::modules::unload_module;
 use crate::shell::Shell;
 
 use anyhow::{anyhow, Result};
 use std::env;
 
-use super::{load_module, Module};
+use super::{builtin_names, load_module_by_name, module_names, unload_module};
 
 pub fn chdir(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
     if argv.len() == 1 {
@@ -13,7 +12,7 @@ pub fn chdir(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
             smsh.set_rv(0);
         }
     } else if argv.len() == 2 {
-        env::set_current_dir(&argv[1])?;
+        env::set_current_dir(argv[1])?;
         smsh.set_rv(0);
     } else {
         smsh.set_rv(0);
@@ -23,43 +22,98 @@ pub fn chdir(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
     Ok(())
 }
 
-pub fn exit(smsh: &mut Shell, _argv: Vec<&str>) -> Result<()> {
-    smsh.set_rv(0);
+// exit [n]
+pub fn exit(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    let rv = match argv.get(1).map(|x| x.parse::<i32>()) {
+        Some(Ok(rv)) => rv,
+        Some(Err(_)) => {
+            smsh.set_rv(2);
+            return Err(anyhow!("exit: {}: Numeric argument required", argv[1]));
+        }
+        None => 0,
+    };
+
+    smsh.set_rv(rv);
+    smsh.finish_history();
     std::process::exit(smsh.state().rv);
 }
 
 pub fn lm_builtin(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
-    if argv.len() == 2 {
-        match argv[1] {
-            "core" => {
-                load_module(smsh, Module::Core);
-                smsh.set_rv(0);
-                Ok(())
-            }
-            _ => {
-                smsh.set_rv(1);
-                Err(anyhow!("Unrecognized module {}", argv[1]))
-            }
-        }
-    } else {
+    if argv.len() != 2 {
         smsh.set_rv(2);
-        Err(anyhow!("Improper invocation of self::load_module"))
+        return Err(anyhow!("Improper invocation of self::load_module"));
+    }
+
+    match load_module_by_name(smsh, argv[1]) {
+        Ok(()) => {
+            smsh.set_rv(0);
+            Ok(())
+        }
+        Err(e) => {
+            smsh.set_rv(1);
+            Err(anyhow!("load_module: {}", e))
+        }
     }
 }
 
 pub fn ulm_builtin(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
-    if argv.len() == 2 {
-        match argv[1] {
-            "core" => unload_module(smsh, Module::Core),
-            _ => {
-                smsh.set_rv(2);
-                Err(anyhow!("unload_module: Unrecognized module {}", argv[1]))
-            }
-        }
-    } else {
-        smsh.set_rv(1);
-        Err(anyhow!(
+    if argv.len() != 2 {
+        smsh.set_rv(2);
+        return Err(anyhow!(
             "unload_module: Improper invocation of self::unload_module"
-        ))
+        ));
+    }
+
+    match unload_module(smsh, argv[1]) {
+        Ok(()) => {
+            smsh.set_rv(0);
+            Ok(())
+        }
+        Err(e) => {
+            smsh.set_rv(1);
+            Err(anyhow!("unload_module: {}", e))
+        }
     }
 }
+
+// Lists every module, marking those that are loaded
+pub fn modules(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    if argv.len() > 1 {
+        smsh.set_rv(2);
+        return Err(anyhow!("self::modules: Too many arguments"));
+    }
+
+    for (name, loaded) in module_names(smsh) {
+        if loaded {
+            println!("{}\tloaded", name);
+        } else {
+            println!("{}", name);
+        }
+    }
+
+    smsh.set_rv(0);
+    Ok(())
+}
+
+// self::builtins [module]
+pub fn builtins(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    if argv.len() > 2 {
+        smsh.set_rv(2);
+        return Err(anyhow!("self::builtins: Too many arguments"));
+    }
+
+    let names = match builtin_names(smsh, argv.get(1).copied()) {
+        Ok(names) => names,
+        Err(e) => {
+            smsh.set_rv(1);
+            return Err(anyhow!("self::builtins: {}", e));
+        }
+    };
+
+    for name in names {
+        println!("{}", name);
+    }
+
+    smsh.set_rv(0);
+    Ok(())
+}
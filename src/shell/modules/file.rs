@@ -0,0 +1,149 @@
+// Builtins for testing the properties of files; see doc/file.md.
+// Each takes exactly one file, and sets rv to 0 if the test holds,
+// 1 if it does not.
+
+use crate::shell::Shell;
+
+use anyhow::{anyhow, Result};
+use nix::sys::stat::{lstat, stat, FileStat, Mode, SFlag};
+use nix::unistd::{access, AccessFlags};
+
+use std::path::Path;
+
+pub fn exists(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| stat(x).is_ok())
+}
+
+// A directory is empty if it has no entries
+pub fn is_empty(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| match stat(x) {
+        Ok(st) if has_type(&st, SFlag::S_IFDIR) => x
+            .read_dir()
+            .map(|mut x| x.next().is_none())
+            .unwrap_or(false),
+        Ok(st) => st.st_size == 0,
+        Err(_) => false,
+    })
+}
+
+pub fn is_regular(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| is_type(x, SFlag::S_IFREG))
+}
+
+pub fn is_directory(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| is_type(x, SFlag::S_IFDIR))
+}
+
+pub fn is_block_special(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| is_type(x, SFlag::S_IFBLK))
+}
+
+pub fn is_character_special(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| is_type(x, SFlag::S_IFCHR))
+}
+
+// The link itself is tested, not what it points to
+pub fn is_symbolic_link(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| match lstat(x) {
+        Ok(st) => has_type(&st, SFlag::S_IFLNK),
+        Err(_) => false,
+    })
+}
+
+pub fn is_fifo(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| is_type(x, SFlag::S_IFIFO))
+}
+
+pub fn is_socket(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| is_type(x, SFlag::S_IFSOCK))
+}
+
+pub fn is_readable(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| access(x, AccessFlags::R_OK).is_ok())
+}
+
+pub fn is_writeable(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| access(x, AccessFlags::W_OK).is_ok())
+}
+
+pub fn is_executable(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| access(x, AccessFlags::X_OK).is_ok())
+}
+
+pub fn has_set_user_id_set(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| has_mode(x, Mode::S_ISUID))
+}
+
+pub fn has_set_group_id_set(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| has_mode(x, Mode::S_ISGID))
+}
+
+pub fn has_sticky_bit_set(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    test_file(smsh, argv, |x| has_mode(x, Mode::S_ISVTX))
+}
+
+fn test_file(smsh: &mut Shell, argv: Vec<&str>, test: fn(&Path) -> bool) -> Result<()> {
+    if argv.len() != 2 {
+        smsh.set_rv(2);
+        return Err(anyhow!("{}: Improper invocation", argv[0]));
+    }
+
+    let rv = if test(Path::new(argv[1])) { 0 } else { 1 };
+    smsh.set_rv(rv);
+
+    Ok(())
+}
+
+fn has_type(st: &FileStat, file_type: SFlag) -> bool {
+    SFlag::from_bits_truncate(st.st_mode) & SFlag::S_IFMT == file_type
+}
+
+// Symbolic links are followed
+fn is_type(path: &Path, file_type: SFlag) -> bool {
+    match stat(path) {
+        Ok(st) => has_type(&st, file_type),
+        Err(_) => false,
+    }
+}
+
+fn has_mode(path: &Path, mode: Mode) -> bool {
+    match stat(path) {
+        Ok(st) => Mode::from_bits_truncate(st.st_mode).contains(mode),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_tests_1() {
+        let mut smsh = Shell::new(false);
+
+        is_directory(&mut smsh, vec!["file::is_directory", "/"]).unwrap();
+        assert_eq!(0, smsh.rv());
+
+        is_regular(&mut smsh, vec!["file::is_regular", "/"]).unwrap();
+        assert_eq!(1, smsh.rv());
+
+        is_character_special(&mut smsh, vec!["file::is_character_special", "/dev/null"]).unwrap();
+        assert_eq!(0, smsh.rv());
+
+        is_empty(&mut smsh, vec!["file::is_empty", "/dev/null"]).unwrap();
+        assert_eq!(0, smsh.rv());
+
+        exists(&mut smsh, vec!["file::exists", "/no/such/file/for/smsh"]).unwrap();
+        assert_eq!(1, smsh.rv());
+
+        has_sticky_bit_set(&mut smsh, vec!["file::has_sticky_bit_set", "/tmp"]).unwrap();
+        assert_eq!(0, smsh.rv());
+    }
+
+    #[test]
+    fn file_tests_2() {
+        let mut smsh = Shell::new(false);
+        assert!(exists(&mut smsh, vec!["file::exists"]).is_err());
+        assert_eq!(2, smsh.rv());
+    }
+}
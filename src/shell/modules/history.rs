@@ -0,0 +1,129 @@
+use crate::line::format_local_time;
+use crate::shell::history::HistoryEntry;
+use crate::shell::Shell;
+
+use anyhow::{anyhow, Result};
+
+use std::env;
+use std::time::Duration;
+
+// history [--here] [n]
+// history search [--here] text
+// history clear
+pub fn history(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    let mut args: Vec<&str> = argv[1..].to_vec();
+
+    if args.first() == Some(&"clear") {
+        if args.len() > 1 {
+            smsh.set_rv(2);
+            return Err(anyhow!("history: Too many arguments"));
+        }
+
+        let res = smsh.history().lock().clear();
+        return match res {
+            Ok(()) => {
+                smsh.set_rv(0);
+                Ok(())
+            }
+            Err(e) => {
+                smsh.set_rv(1);
+                Err(anyhow!("history: {}", e))
+            }
+        };
+    }
+
+    let search = args.first() == Some(&"search");
+    if search {
+        args.remove(0);
+    }
+
+    let here = args.first() == Some(&"--here");
+    if here {
+        args.remove(0);
+    }
+
+    let (text, count) = match (search, args.as_slice()) {
+        (true, [text]) => (Some(*text), None),
+        (false, []) => (None, None),
+        (false, [n]) => match n.parse::<usize>() {
+            Ok(n) => (None, Some(n)),
+            Err(_) => {
+                smsh.set_rv(2);
+                return Err(anyhow!("history: {}: Numeric argument required", n));
+            }
+        },
+        _ => {
+            smsh.set_rv(2);
+            return Err(anyhow!("history: Improper invocation of `history`"));
+        }
+    };
+
+    let cwd = env::current_dir()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let history = smsh.history().clone();
+    let history = history.lock();
+
+    let entries: Vec<(usize, &HistoryEntry)> = history
+        .entries()
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| !here || x.cwd == cwd)
+        .filter(|(_, x)| text.is_none_or(|text| x.command.contains(text)))
+        .collect();
+
+    let skip = count.map_or(0, |n| entries.len().saturating_sub(n));
+
+    for (i, entry) in &entries[skip..] {
+        println!("{}", format_entry(i + 1, entry));
+    }
+
+    // Like grep, a search that finds nothing fails
+    smsh.set_rv(if search && entries.is_empty() { 1 } else { 0 });
+    Ok(())
+}
+
+//      12  2022-10-17 14:03     0    1.2s  cargo build
+fn format_entry(number: usize, entry: &HistoryEntry) -> String {
+    let time = format_local_time(entry.timestamp as libc::time_t, "%Y-%m-%d %H:%M");
+
+    let status = match entry.status {
+        Some(status) => status.to_string(),
+        None => "-".to_string(),
+    };
+
+    let duration = match entry.duration {
+        Some(duration) => format_duration(duration),
+        None => "-".to_string(),
+    };
+
+    format!(
+        "{:>6}  {}  {:>4}  {:>6}  {}",
+        number, time, status, duration, entry.command
+    )
+}
+
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else if millis < 60_000 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else {
+        format!("{}m{}s", millis / 60_000, (millis % 60_000) / 1000)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_duration_1() {
+        assert_eq!("15ms", format_duration(Duration::from_millis(15)));
+        assert_eq!("1.5s", format_duration(Duration::from_millis(1500)));
+        assert_eq!("2m5s", format_duration(Duration::from_secs(125)));
+    }
+}
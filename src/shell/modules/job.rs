@@ -0,0 +1,192 @@
+use crate::shell::jobs::JobState;
+use crate::shell::Shell;
+
+use anyhow::{anyhow, Result};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+pub fn jobs(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    if argv.len() > 1 {
+        smsh.set_rv(2);
+        return Err(anyhow!("jobs: Too many arguments"));
+    }
+
+    let jobs = smsh.jobs();
+    jobs.poll();
+
+    for job in jobs.iter() {
+        println!("{}", job.status_line(jobs.marker(job)));
+    }
+
+    smsh.set_rv(0);
+    Ok(())
+}
+
+pub fn fg(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    if argv.len() > 2 {
+        smsh.set_rv(2);
+        return Err(anyhow!("fg: Too many arguments"));
+    }
+
+    let spec = argv.get(1).copied();
+
+    match smsh.jobs().get(spec) {
+        Ok(job) => println!("{}", job.command()),
+        Err(e) => {
+            smsh.set_rv(1);
+            return Err(anyhow!("fg: {}", e));
+        }
+    }
+
+    let rv = match smsh.jobs().resume_in_foreground(spec) {
+        Ok(rv) => rv,
+        Err(e) => {
+            smsh.set_rv(1);
+            return Err(anyhow!("fg: {}", e));
+        }
+    };
+    smsh.set_rv(rv);
+
+    Ok(())
+}
+
+pub fn bg(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    if argv.len() > 2 {
+        smsh.set_rv(2);
+        return Err(anyhow!("bg: Too many arguments"));
+    }
+
+    let job = match smsh.jobs().get(argv.get(1).copied()) {
+        Ok(job) => job,
+        Err(e) => {
+            smsh.set_rv(1);
+            return Err(anyhow!("bg: {}", e));
+        }
+    };
+
+    if job.state() == JobState::Stopped {
+        if let Err(e) = job.resume() {
+            smsh.set_rv(1);
+            return Err(anyhow!("bg: {}", e));
+        }
+    }
+
+    println!("[{}] {} &", job.id(), job.command());
+
+    smsh.set_rv(0);
+    Ok(())
+}
+
+// With no arguments, waits on every job.  Otherwise, waits on
+// each job or pid given, and returns the last one's return value.
+pub fn wait(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    let specs: Vec<String> = if argv.len() == 1 {
+        smsh.jobs().iter().map(|x| format!("%{}", x.id())).collect()
+    } else {
+        argv[1..].iter().map(|x| x.to_string()).collect()
+    };
+
+    smsh.set_rv(0);
+
+    for spec in specs {
+        let mut job = match smsh.jobs().remove(Some(&spec)) {
+            Ok(job) => job,
+            Err(e) => {
+                smsh.set_rv(127);
+                return Err(anyhow!("wait: {}", e));
+            }
+        };
+
+        let res = job.wait();
+        let rv = job.rv();
+
+        if job.state() == JobState::Stopped {
+            smsh.jobs().insert(job);
+        }
+
+        res?;
+        smsh.set_rv(rv);
+    }
+
+    Ok(())
+}
+
+// kill [-SIGNAL | -s SIGNAL] (%job | pid)...
+pub fn kill(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    let args = &argv[1..];
+
+    let sig = if args.len() >= 2 && args[0] == "-s" {
+        Some(parse_signal(args[1]))
+    } else {
+        args.first()
+            .and_then(|x| x.strip_prefix('-'))
+            .map(parse_signal)
+    };
+
+    let (sig, targets) = match sig {
+        Some(Ok(sig)) if args[0] == "-s" => (sig, &args[2..]),
+        Some(Ok(sig)) => (sig, &args[1..]),
+        Some(Err(e)) => {
+            smsh.set_rv(2);
+            return Err(e);
+        }
+        None => (Signal::SIGTERM, args),
+    };
+
+    if targets.is_empty() {
+        smsh.set_rv(2);
+        return Err(anyhow!("kill: Improper invocation of `kill`"));
+    }
+
+    smsh.set_rv(0);
+
+    for target in targets {
+        let res = if target.starts_with('%') {
+            match smsh.jobs().get(Some(target)) {
+                Ok(job) => {
+                    // A stopped job must be continued to act on its signal
+                    let res = job.signal(sig);
+                    if job.state() == JobState::Stopped
+                        && (sig == Signal::SIGTERM || sig == Signal::SIGHUP)
+                    {
+                        let _ = job.signal(Signal::SIGCONT);
+                    }
+                    res.map_err(|e| anyhow!("{}: {}", target, e))
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            match target.parse::<i32>() {
+                Ok(pid) => signal::kill(Pid::from_raw(pid), sig)
+                    .map_err(|e| anyhow!("{}: {}", target, e.desc())),
+                Err(_) => Err(anyhow!("{}: Not a pid or job specification", target)),
+            }
+        };
+
+        if let Err(e) = res {
+            smsh.set_rv(1);
+            eprintln!("kill: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+// Accepts signal numbers and names, with or without the `SIG` prefix
+fn parse_signal(name: &str) -> Result<Signal> {
+    if let Ok(n) = name.parse::<i32>() {
+        return Signal::try_from(n).map_err(|_| anyhow!("kill: {}: Invalid signal", name));
+    }
+
+    let name = name.to_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+
+    Signal::from_str(&name).map_err(|_| anyhow!("kill: {}: Invalid signal", name))
+}
@@ -1,28 +1,292 @@
 use super::Shell;
 use anyhow::{anyhow, Result};
 
+mod completion;
 mod core;
+mod file;
+mod history;
+mod job;
+mod plugin;
+pub use plugin::Plugin;
+mod script;
+
+// What a loaded module has added to the shell
+#[derive(Default)]
+pub struct LoadedModule {
+    builtins: Vec<String>,
+    functions: Vec<String>, // Of script modules, in the root scope
+}
 
 pub type Builtin = fn(&mut Shell, Vec<&str>) -> Result<()>;
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Module {
     Core,
+    Job,
+    File,
+    History,
+    Completion,
 }
 
+impl Module {
+    pub const ALL: [Module; 5] = [
+        Module::Core,
+        Module::Job,
+        Module::File,
+        Module::History,
+        Module::Completion,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Module> {
+        Module::ALL.into_iter().find(|x| x.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Module::Core => "core",
+            Module::Job => "job",
+            Module::File => "file",
+            Module::History => "history",
+            Module::Completion => "completion",
+        }
+    }
+
+    fn builtins(&self) -> &'static [(&'static str, Builtin)] {
+        match self {
+            Module::Core => &CORE_BUILTINS,
+            Module::Job => &JOB_BUILTINS,
+            Module::File => &FILE_BUILTINS,
+            Module::History => &HISTORY_BUILTINS,
+            Module::Completion => &COMPLETION_BUILTINS,
+        }
+    }
+}
+
+const CORE_BUILTINS: [(&str, Builtin); 6] = [
+    ("cd", core::chdir),
+    ("exit", core::exit),
+    ("self::load_module", core::lm_builtin),
+    ("self::unload_module", core::ulm_builtin),
+    ("self::modules", core::modules),
+    ("self::builtins", core::builtins),
+];
+
+const JOB_BUILTINS: [(&str, Builtin); 5] = [
+    ("jobs", job::jobs),
+    ("fg", job::fg),
+    ("bg", job::bg),
+    ("wait", job::wait),
+    ("kill", job::kill),
+];
+
+const HISTORY_BUILTINS: [(&str, Builtin); 1] = [("history", history::history)];
+
+const COMPLETION_BUILTINS: [(&str, Builtin); 1] = [("complete", completion::complete)];
+
+const FILE_BUILTINS: [(&str, Builtin); 15] = [
+    ("file::exists", file::exists),
+    ("file::is_empty", file::is_empty),
+    ("file::is_regular", file::is_regular),
+    ("file::is_directory", file::is_directory),
+    ("file::is_block_special", file::is_block_special),
+    ("file::is_character_special", file::is_character_special),
+    ("file::is_symbolic_link", file::is_symbolic_link),
+    ("file::is_fifo", file::is_fifo),
+    ("file::is_socket", file::is_socket),
+    ("file::is_readable", file::is_readable),
+    ("file::is_writeable", file::is_writeable),
+    ("file::is_executable", file::is_executable),
+    ("file::has_set_user_id_set", file::has_set_user_id_set),
+    ("file::has_set_group_id_set", file::has_set_group_id_set),
+    ("file::has_sticky_bit_set", file::has_sticky_bit_set),
+];
+
+// Loading a module that is already loaded does nothing
 pub fn load_module(smsh: &mut Shell, module: Module) {
-    match module {
-        Module::Core => {
-            smsh.builtins.insert("cd", core::chdir);
-            smsh.builtins.insert("exit", core::exit);
-            smsh.builtins.insert("self::load_module", core::lm_builtin);
-            smsh.builtins
-                .insert("self::unload_module", core::ulm_builtin);
+    if smsh.is_module_loaded(module.name()) {
+        return;
+    }
+
+    let builtins = module
+        .builtins()
+        .iter()
+        .map(|(name, builtin)| (name.to_string(), *builtin))
+        .collect();
+
+    register_module(smsh, module.name(), builtins);
+}
+
+// Loads a compiled-in module by name, else a script module by name,
+// else a native one by name or path
+pub fn load_module_by_name(smsh: &mut Shell, name: &str) -> Result<()> {
+    if let Some(module) = Module::from_name(name) {
+        load_module(smsh, module);
+        Ok(())
+    } else if let Some(path) = script::find_script_module(name) {
+        script::load_script_module(smsh, name, path)
+    } else {
+        plugin::load_plugin(smsh, name)
+    }
+}
+
+// A builtin sharing its name with one from another module replaces it,
+// and belongs to the module registered last.
+fn register_module(smsh: &mut Shell, module: &str, builtins: Vec<(String, Builtin)>) {
+    let mut owned = Vec::<String>::new();
+
+    for (name, builtin) in builtins {
+        for loaded in smsh.modules.values_mut() {
+            loaded.builtins.retain(|x| *x != name);
         }
+
+        smsh.builtins.insert(name.clone(), builtin);
+        owned.push(name);
     }
+
+    let loaded = LoadedModule {
+        builtins: owned,
+        ..Default::default()
+    };
+
+    smsh.modules.insert(module.to_string(), loaded);
+}
+
+// The loaded module that owns builtin `name`
+pub fn owner<'a>(smsh: &'a Shell, name: &str) -> Option<&'a str> {
+    smsh.modules
+        .iter()
+        .find(|(_, loaded)| loaded.builtins.iter().any(|x| x == name))
+        .map(|(module, _)| module.as_str())
 }
 
-pub fn unload_module(_smsh: &mut Shell, module: Module) -> Result<()> {
-    match module {
-        Module::Core => Err(anyhow!("Unable to unload smsh core module!")),
+// Removes exactly the builtins and functions the module owns
+pub fn unload_module(smsh: &mut Shell, name: &str) -> Result<()> {
+    if name == Module::Core.name() {
+        return Err(anyhow!("Unable to unload smsh core module!"));
+    }
+
+    let loaded = match smsh.modules.remove(name) {
+        Some(loaded) => loaded,
+        None if is_available(name) => return Err(anyhow!("{}: Module is not loaded", name)),
+        None => return Err(anyhow!("{}: Unrecognized module", name)),
+    };
+
+    for builtin in loaded.builtins {
+        smsh.builtins.remove(&builtin);
+    }
+
+    for function in loaded.functions {
+        smsh.remove_user_function(&function, true);
+    }
+
+    // Only once nothing refers to its builtins
+    smsh.plugins.remove(name);
+
+    Ok(())
+}
+
+// Names of the loaded modules' builtins, or of those of `module` alone
+pub fn builtin_names(smsh: &Shell, module: Option<&str>) -> Result<Vec<String>> {
+    let mut names = match module {
+        Some(name) => match smsh.modules.get(name) {
+            Some(loaded) => loaded.builtins.clone(),
+            None if is_available(name) => return Err(anyhow!("{}: Module is not loaded", name)),
+            None => return Err(anyhow!("{}: Unrecognized module", name)),
+        },
+        None => smsh.builtins.keys().cloned().collect(),
+    };
+
+    names.sort_unstable();
+
+    Ok(names)
+}
+
+// Every module that is loaded or could be, with whether it is loaded
+pub fn module_names(smsh: &Shell) -> Vec<(String, bool)> {
+    let mut names: Vec<String> = Module::ALL.iter().map(|x| x.name().to_string()).collect();
+
+    for name in script::available_script_modules()
+        .into_iter()
+        .chain(plugin::available_plugins())
+        .chain(smsh.modules.keys().cloned())
+    {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|x| {
+            let loaded = smsh.is_module_loaded(&x);
+            (x, loaded)
+        })
+        .collect()
+}
+
+fn is_available(name: &str) -> bool {
+    Module::from_name(name).is_some()
+        || script::find_script_module(name).is_some()
+        || plugin::find_plugin(name).is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn modules_1() {
+        let mut smsh = Shell::new(false);
+        let count = smsh.builtins.len();
+
+        load_module(&mut smsh, Module::File);
+        assert!(smsh.get_builtin("file::exists").is_some());
+        assert_eq!(count + FILE_BUILTINS.len(), smsh.builtins.len());
+
+        // Reloading is idempotent
+        load_module(&mut smsh, Module::File);
+        assert_eq!(count + FILE_BUILTINS.len(), smsh.builtins.len());
+
+        unload_module(&mut smsh, "file").unwrap();
+        assert!(smsh.get_builtin("file::exists").is_none());
+        assert_eq!(count, smsh.builtins.len());
+
+        assert!(unload_module(&mut smsh, "file").is_err());
+    }
+
+    #[test]
+    fn modules_2() {
+        let mut smsh = Shell::new(false);
+
+        assert!(unload_module(&mut smsh, "core").is_err());
+        assert!(smsh.get_builtin("cd").is_some());
+
+        assert_eq!(
+            vec!["bg", "fg", "jobs", "kill", "wait"],
+            builtin_names(&smsh, Some("job")).unwrap()
+        );
+
+        unload_module(&mut smsh, "job").unwrap();
+        assert!(smsh.get_builtin("jobs").is_none());
+        assert!(smsh.get_builtin("cd").is_some());
+        assert!(builtin_names(&smsh, Some("job")).is_err());
+    }
+
+    #[test]
+    fn owner_1() {
+        let mut smsh = Shell::new(false);
+        assert_eq!(Some("job"), owner(&smsh, "fg"));
+        assert_eq!(None, owner(&smsh, "file::exists"));
+
+        // Registered last owns the name
+        register_module(
+            &mut smsh,
+            "other",
+            vec![("fg".to_string(), core::chdir as Builtin)],
+        );
+        assert_eq!(Some("other"), owner(&smsh, "fg"));
+
+        unload_module(&mut smsh, "other").unwrap();
+        assert_eq!(None, owner(&smsh, "fg"));
     }
 }
@@ -0,0 +1,469 @@
+// Native modules are shared libraries, found in `smsh/modules` under the
+// XDG data directories, or given by path.  Each exports a registration
+// entry point whose name carries the version of the interface it was
+// built against, and through which it adds its builtins.  Those are
+// called with a narrow host interface in place of the Shell itself.
+// See doc/modules.md.
+
+use super::{register_module, Builtin};
+use crate::shell::variables::{get_special_variable, is_special_variable};
+use crate::shell::Shell;
+
+use anyhow::{anyhow, Result};
+use libc::{c_char, c_int, c_void, size_t, ssize_t};
+use nix::unistd;
+use xdg::BaseDirectories;
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
+
+pub const API_VERSION: u32 = 1;
+const REGISTER_SYMBOL: &str = "smsh_module_register_v1";
+
+// Returns the value to which rv is set
+pub type PluginBuiltin =
+    extern "C" fn(host: *const Host, argc: c_int, argv: *const *const c_char) -> c_int;
+
+type Register = unsafe extern "C" fn(registrar: *mut Registrar) -> c_int;
+
+// Everything a native builtin may do to the shell.  Strings are
+// NUL-terminated UTF-8; functions returning ssize_t return -1 on failure.
+#[repr(C)]
+pub struct Host {
+    pub version: u32,
+    pub shell: *mut c_void, // Opaque; passed back to each function
+    // rv as set by the previous command
+    pub rv: extern "C" fn(shell: *mut c_void) -> c_int,
+    // Copies at most `len - 1` bytes of the value, and a NUL, into `buf`.
+    // Returns the full length of the value.
+    pub get_variable: extern "C" fn(
+        shell: *mut c_void,
+        name: *const c_char,
+        buf: *mut c_char,
+        len: size_t,
+    ) -> ssize_t,
+    // Sets a variable in the current scope; returns 0 on success
+    pub set_variable:
+        extern "C" fn(shell: *mut c_void, name: *const c_char, value: *const c_char) -> c_int,
+    pub read: extern "C" fn(shell: *mut c_void, buf: *mut c_void, len: size_t) -> ssize_t,
+    // To standard output (fd 1) or standard error (fd 2)
+    pub write:
+        extern "C" fn(shell: *mut c_void, fd: c_int, buf: *const c_void, len: size_t) -> ssize_t,
+}
+
+// Passed to the registration entry point
+#[repr(C)]
+pub struct Registrar {
+    pub version: u32,
+    pub context: *mut c_void,
+    // Adds `name` as `<module>::<name>`; returns 0 on success
+    pub add_builtin:
+        extern "C" fn(context: *mut c_void, name: *const c_char, builtin: PluginBuiltin) -> c_int,
+}
+
+pub struct Plugin {
+    handle: *mut c_void,
+    builtins: HashMap<String, PluginBuiltin>, // By full name
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+// Loading a plugin that is already loaded does nothing
+pub fn load_plugin(smsh: &mut Shell, name: &str) -> Result<()> {
+    let path = match find_plugin(name) {
+        Some(path) => path,
+        None => return Err(anyhow!("{}: Unrecognized module", name)),
+    };
+
+    let module = module_name(&path);
+
+    if super::Module::from_name(&module).is_some() {
+        return Err(anyhow!("{}: Conflicts with a builtin module", module));
+    }
+
+    if smsh.is_module_loaded(&module) {
+        return Ok(());
+    }
+
+    let plugin = open_plugin(&path, &module)?;
+
+    let builtins = plugin
+        .builtins
+        .keys()
+        .map(|x| (x.to_string(), call_plugin as Builtin))
+        .collect();
+
+    register_module(smsh, &module, builtins);
+    smsh.plugins.insert(module, plugin);
+
+    Ok(())
+}
+
+// A name containing a slash is a path; otherwise `lib<name>.so` or
+// `<name>.so` is looked for in the XDG data directories
+pub fn find_plugin(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return if path.is_file() { Some(path) } else { None };
+    }
+
+    let base_dirs = BaseDirectories::with_prefix("smsh").ok()?;
+
+    [
+        format!("modules/lib{}.so", name),
+        format!("modules/{}.so", name),
+    ]
+    .iter()
+    .find_map(|x| base_dirs.find_data_file(x))
+}
+
+// Names of the plugins in the XDG data directories
+pub fn available_plugins() -> Vec<String> {
+    let base_dirs = match BaseDirectories::with_prefix("smsh") {
+        Ok(base_dirs) => base_dirs,
+        Err(_) => return vec![],
+    };
+
+    let mut names: Vec<String> = base_dirs
+        .list_data_files("modules")
+        .iter()
+        .filter(|x| x.extension().map(|x| x == "so").unwrap_or(false))
+        .map(|x| module_name(x))
+        .collect();
+
+    names.sort();
+    names.dedup();
+
+    names
+}
+
+// `libhello.so` and `hello.so` are both the `hello` module
+fn module_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    match stem.strip_prefix("lib") {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => stem,
+    }
+}
+
+fn open_plugin(path: &Path, module: &str) -> Result<Plugin> {
+    let filename = CString::new(path.as_os_str().as_bytes())?;
+    let symbol = CString::new(REGISTER_SYMBOL)?;
+
+    let handle = unsafe { libc::dlopen(filename.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    if handle.is_null() {
+        return Err(anyhow!("{}", dl_error()));
+    }
+
+    // From here on, dropping the plugin closes it
+    let mut plugin = Plugin {
+        handle,
+        builtins: HashMap::new(),
+    };
+
+    let register = unsafe { libc::dlsym(handle, symbol.as_ptr()) };
+    if register.is_null() {
+        return Err(anyhow!(
+            "{}: Not an smsh module, or not built for version {} of the module interface",
+            path.display(),
+            API_VERSION
+        ));
+    }
+
+    let mut added = Vec::<(String, PluginBuiltin)>::new();
+    let mut registrar = Registrar {
+        version: API_VERSION,
+        context: &mut added as *mut Vec<(String, PluginBuiltin)> as *mut c_void,
+        add_builtin,
+    };
+
+    let rv = unsafe {
+        let register: Register = std::mem::transmute(register);
+        register(&mut registrar)
+    };
+
+    if rv != 0 {
+        return Err(anyhow!("{}: Registration failed ({})", path.display(), rv));
+    }
+
+    for (name, builtin) in added {
+        plugin
+            .builtins
+            .insert(format!("{}::{}", module, name), builtin);
+    }
+
+    Ok(plugin)
+}
+
+fn dl_error() -> String {
+    unsafe {
+        let error = libc::dlerror();
+        if error.is_null() {
+            "Unable to load module".to_string()
+        } else {
+            CStr::from_ptr(error).to_string_lossy().to_string()
+        }
+    }
+}
+
+// The Builtin through which every native builtin is called
+fn call_plugin(smsh: &mut Shell, argv: Vec<&str>) -> Result<()> {
+    // Dispatched by the module owning the name, not by whichever plugin
+    // happens to define it
+    let builtin = super::owner(smsh, argv[0])
+        .and_then(|x| smsh.plugins.get(x))
+        .and_then(|x| x.builtins.get(argv[0]))
+        .copied();
+
+    let builtin = match builtin {
+        Some(builtin) => builtin,
+        None => {
+            smsh.set_rv(127);
+            return Err(anyhow!("{}: Module is not loaded", argv[0]));
+        }
+    };
+
+    let args = argv
+        .iter()
+        .map(|x| CString::new(*x))
+        .collect::<Result<Vec<CString>, _>>()?;
+
+    let mut pointers: Vec<*const c_char> = args.iter().map(|x| x.as_ptr()).collect();
+    pointers.push(ptr::null());
+
+    // Whatever the shell has written so far comes first
+    io::stdout().flush()?;
+
+    let host = Host {
+        version: API_VERSION,
+        shell: smsh as *mut Shell as *mut c_void,
+        rv: host_rv,
+        get_variable: host_get_variable,
+        set_variable: host_set_variable,
+        read: host_read,
+        write: host_write,
+    };
+
+    let rv = builtin(&host, args.len() as c_int, pointers.as_ptr());
+    smsh.set_rv(rv);
+
+    Ok(())
+}
+
+extern "C" fn add_builtin(
+    context: *mut c_void,
+    name: *const c_char,
+    builtin: PluginBuiltin,
+) -> c_int {
+    let added = unsafe { &mut *(context as *mut Vec<(String, PluginBuiltin)>) };
+
+    match to_str(name) {
+        Some(name) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+            added.push((name.to_string(), builtin));
+            0
+        }
+        _ => -1,
+    }
+}
+
+// Only ever given the pointer made from `&mut Shell` in call_plugin,
+// which is not otherwise used until the builtin returns
+fn shell<'a>(shell: *mut c_void) -> &'a mut Shell {
+    unsafe { &mut *(shell as *mut Shell) }
+}
+
+fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+
+    unsafe { CStr::from_ptr(s) }.to_str().ok()
+}
+
+extern "C" fn host_rv(smsh: *mut c_void) -> c_int {
+    shell(smsh).rv()
+}
+
+extern "C" fn host_get_variable(
+    smsh: *mut c_void,
+    name: *const c_char,
+    buf: *mut c_char,
+    len: size_t,
+) -> ssize_t {
+    let smsh = shell(smsh);
+
+    let name = match to_str(name) {
+        Some(name) => name,
+        None => return -1,
+    };
+
+    let value = match get_special_variable(smsh, name).or_else(|| smsh.get_user_variable(name)) {
+        Some(value) => value,
+        None => return -1,
+    };
+
+    if !buf.is_null() && len > 0 {
+        let n = value.len().min(len - 1);
+        unsafe {
+            ptr::copy_nonoverlapping(value.as_ptr(), buf as *mut u8, n);
+            *buf.add(n) = 0;
+        }
+    }
+
+    value.len() as ssize_t
+}
+
+extern "C" fn host_set_variable(
+    smsh: *mut c_void,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    let (name, value) = match (to_str(name), to_str(value)) {
+        (Some(name), Some(value)) => (name, value),
+        _ => return -1,
+    };
+
+    if name.is_empty() || is_special_variable(name) {
+        return -1;
+    }
+
+    shell(smsh).insert_user_variable(name.to_string(), value.to_string(), false);
+
+    0
+}
+
+extern "C" fn host_read(_smsh: *mut c_void, buf: *mut c_void, len: size_t) -> ssize_t {
+    if buf.is_null() {
+        return -1;
+    }
+
+    let buf = unsafe { slice::from_raw_parts_mut(buf as *mut u8, len) };
+
+    match unistd::read(0, buf) {
+        Ok(n) => n as ssize_t,
+        Err(_) => -1,
+    }
+}
+
+extern "C" fn host_write(
+    _smsh: *mut c_void,
+    fd: c_int,
+    buf: *const c_void,
+    len: size_t,
+) -> ssize_t {
+    if buf.is_null() || (fd != 1 && fd != 2) {
+        return -1;
+    }
+
+    let buf = unsafe { slice::from_raw_parts(buf as *const u8, len) };
+
+    match unistd::write(fd, buf) {
+        Ok(n) => n as ssize_t,
+        Err(_) => -1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shell::modules::{load_module_by_name, unload_module};
+
+    use std::env;
+    use std::process::Command;
+
+    // Builds the sample plugin in plugins/hello
+    fn build_sample_plugin() -> PathBuf {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let target_dir = root.join("target/sample-plugin");
+
+        let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+            .arg("build")
+            .arg("--quiet")
+            .arg("--manifest-path")
+            .arg(root.join("plugins/hello/Cargo.toml"))
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        target_dir.join("debug/libhello.so")
+    }
+
+    #[test]
+    fn module_name_1() {
+        assert_eq!("hello", module_name(Path::new("/a/libhello.so")));
+        assert_eq!("hello", module_name(Path::new("hello.so")));
+        assert_eq!("lib", module_name(Path::new("lib.so")));
+    }
+
+    #[test]
+    fn plugin_1() {
+        let path = build_sample_plugin();
+        let path = path.to_str().unwrap();
+
+        let mut smsh = Shell::new(false);
+        load_module_by_name(&mut smsh, path).unwrap();
+        assert!(smsh.is_module_loaded("hello"));
+
+        // Reloading is idempotent
+        load_module_by_name(&mut smsh, path).unwrap();
+        assert_eq!(1, smsh.plugins.len());
+
+        let count = *smsh.get_builtin("hello::count").unwrap();
+        count(&mut smsh, vec!["hello::count", "a", "b", "c"]).unwrap();
+        assert_eq!(3, smsh.rv());
+
+        let remember = *smsh.get_builtin("hello::remember").unwrap();
+        remember(&mut smsh, vec!["hello::remember", "greeting", "hi"]).unwrap();
+        assert_eq!(0, smsh.rv());
+        assert_eq!(Some("hi".to_string()), smsh.get_user_variable("greeting"));
+
+        // Special variables are readable, but not writeable
+        remember(&mut smsh, vec!["hello::remember", "rv", "5"]).unwrap();
+        assert_eq!(1, smsh.rv());
+
+        let length = *smsh.get_builtin("hello::length").unwrap();
+        length(&mut smsh, vec!["hello::length", "greeting"]).unwrap();
+        assert_eq!(2, smsh.rv());
+
+        unload_module(&mut smsh, "hello").unwrap();
+        assert!(smsh.get_builtin("hello::count").is_none());
+        assert!(smsh.plugins.is_empty());
+    }
+
+    #[test]
+    fn plugin_2() {
+        let path = build_sample_plugin();
+
+        let mut smsh = Shell::new(false);
+        load_module_by_name(&mut smsh, path.to_str().unwrap()).unwrap();
+
+        // Once another module owns the name, the plugin no longer answers to it
+        super::super::register_module(
+            &mut smsh,
+            "other",
+            vec![("hello::count".to_string(), call_plugin as Builtin)],
+        );
+
+        let count = *smsh.get_builtin("hello::count").unwrap();
+        assert!(count(&mut smsh, vec!["hello::count", "a"]).is_err());
+        assert_eq!(127, smsh.rv());
+    }
+}
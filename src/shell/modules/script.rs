@@ -0,0 +1,188 @@
+// Script modules are smsh scripts, `<name>.smsh`, found in `smsh/modules`
+// under the XDG data directories.  On loading, a script runs in a scope
+// of its own; the functions it defines there are kept in the root scope
+// as `<name>::<function>`, and everything else it defines is discarded.
+// A module may not define functions outside its scope, with `fn --global`,
+// as those would escape being unloaded with it.
+
+use super::LoadedModule;
+use crate::shell::Shell;
+use crate::sources::script::Script;
+
+use anyhow::{anyhow, Result};
+use xdg::BaseDirectories;
+
+use std::path::PathBuf;
+
+// Loading a module that is already loaded does nothing
+pub fn load_script_module(smsh: &mut Shell, name: &str, path: PathBuf) -> Result<()> {
+    if smsh.is_module_loaded(name) {
+        return Ok(());
+    }
+
+    let script = Script::build_source(path, None)?;
+
+    let outer = smsh.state.set_loading_module(Some(name.to_string()));
+    smsh.scopes.push(vec![name.to_string()]);
+    let res = smsh.run_source(script);
+    let functions = smsh.scopes.take_functions();
+    smsh.scopes.pop();
+    smsh.state.set_loading_module(outer);
+
+    if let Err(e) = res {
+        return Err(anyhow!("{}: Unable to load module\n{}", name, e));
+    }
+
+    let mut owned = Vec::<String>::new();
+
+    for func in functions {
+        let full_name = format!("{}::{}", name, func.name());
+        smsh.insert_user_function(func.with_name(full_name.clone()), true);
+        owned.push(full_name);
+    }
+
+    owned.sort();
+
+    let loaded = LoadedModule {
+        functions: owned,
+        ..Default::default()
+    };
+
+    smsh.modules.insert(name.to_string(), loaded);
+
+    Ok(())
+}
+
+pub fn find_script_module(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+
+    let base_dirs = BaseDirectories::with_prefix("smsh").ok()?;
+    base_dirs.find_data_file(format!("modules/{}.smsh", name))
+}
+
+// Names of the script modules in the XDG data directories
+pub fn available_script_modules() -> Vec<String> {
+    let base_dirs = match BaseDirectories::with_prefix("smsh") {
+        Ok(base_dirs) => base_dirs,
+        Err(_) => return vec![],
+    };
+
+    let mut names: Vec<String> = base_dirs
+        .list_data_files("modules")
+        .iter()
+        .filter(|x| x.extension().map(|x| x == "smsh").unwrap_or(false))
+        .filter_map(|x| x.file_stem())
+        .map(|x| x.to_string_lossy().to_string())
+        .collect();
+
+    names.sort();
+    names.dedup();
+
+    names
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::line::Line;
+    use crate::shell::modules::unload_module;
+    use crate::sources::SourceKind;
+
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn script_module_1() {
+        let dir = env::temp_dir().join(format!("smsh-script-module-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("util.smsh");
+        fs::write(&path, "let x = 1\nfn greet:\n    echo hello\n").unwrap();
+
+        let mut smsh = Shell::new(false);
+        load_script_module(&mut smsh, "util", path.clone()).unwrap();
+
+        assert!(smsh.get_user_function("util::greet").is_some());
+        assert!(smsh.get_user_function("greet").is_none());
+        assert!(smsh.get_user_variable("x").is_none());
+
+        // Reloading is idempotent
+        load_script_module(&mut smsh, "util", path).unwrap();
+        assert!(smsh.get_user_function("util::greet").is_some());
+
+        unload_module(&mut smsh, "util").unwrap();
+        assert!(smsh.get_user_function("util::greet").is_none());
+        assert!(!smsh.is_module_loaded("util"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn script_module_2() {
+        let dir = env::temp_dir().join(format!("smsh-script-module-2-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // A module's functions call one another by their names within it
+        let path = dir.join("calls.smsh");
+        let text = [
+            "fn mark:",
+            "    let --global marked = yes",
+            "fn greet:",
+            "    mark",
+            "fn test_mark:",
+            "    if mark",
+            "        let --global tested = yes",
+            "    while not mark",
+            "        let --global looped = yes",
+        ]
+        .join("\n");
+        fs::write(&path, text).unwrap();
+
+        let mut smsh = Shell::new(false);
+        load_script_module(&mut smsh, "calls", path).unwrap();
+
+        let mut line = Line::new("calls::greet".to_string(), 1, SourceKind::Subshell).unwrap();
+        line.execute(&mut smsh).unwrap();
+        assert_eq!(Some("yes".to_string()), smsh.get_user_variable("marked"));
+
+        // Conditionals included
+        let mut line = Line::new("calls::test_mark".to_string(), 1, SourceKind::Subshell).unwrap();
+        line.execute(&mut smsh).unwrap();
+        smsh.run().unwrap();
+        assert_eq!(Some("yes".to_string()), smsh.get_user_variable("tested"));
+        assert!(smsh.get_user_variable("looped").is_none());
+        assert_eq!(0, smsh.rv());
+
+        // Nor may it define functions it would not own
+        let path = dir.join("leaky.smsh");
+        fs::write(&path, "fn --global leak:\n    echo leak\n").unwrap();
+
+        assert!(load_script_module(&mut smsh, "leaky", path).is_err());
+        assert!(smsh.get_user_function("leak").is_none());
+        assert!(!smsh.is_module_loaded("leaky"));
+        assert!(smsh.state().loading_module().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn script_module_3() {
+        let dir = env::temp_dir().join(format!("smsh-script-module-3-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // What a failed module had left to run is discarded
+        let path = dir.join("broken.smsh");
+        fs::write(&path, "true >\nlet --global leftover = 1\n").unwrap();
+
+        let mut smsh = Shell::new(false);
+        assert!(load_script_module(&mut smsh, "broken", path).is_err());
+        smsh.run().unwrap();
+
+        assert!(smsh.get_user_variable("leftover").is_none());
+        assert!(!smsh.is_module_loaded("broken"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
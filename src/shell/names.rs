@@ -0,0 +1,62 @@
+// What the terminal knows of the shell while reading a line:  its
+// builtins, functions, variables, modules and completion rules.  The
+// shell refreshes them before each prompt, so that whatever was defined
+// or loaded by the last line is known to completion.
+
+use super::completion::Completions;
+use super::modules::module_names;
+use super::Shell;
+use crate::sources::user_function::UserFunction;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Default)]
+pub struct Names {
+    pub builtins: Vec<String>,
+    pub functions: Vec<UserFunction>,
+    pub variables: Vec<(String, String)>, // Outermost scope first
+    pub modules: Vec<(String, bool)>,     // With whether each is loaded
+    pub completions: Completions,
+
+    // Words printed by completion functions, by function and arguments,
+    // so that each runs once per line rather than on each Tab
+    pub function_words: HashMap<(String, Vec<String>), Vec<String>>,
+}
+
+// Shared by the shell and its terminal
+#[derive(Clone, Default)]
+pub struct SharedNames(Arc<Mutex<Names>>);
+
+impl SharedNames {
+    // A panic while holding the lock leaves the names usable
+    pub fn lock(&self) -> MutexGuard<'_, Names> {
+        match self.0.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl Names {
+    pub fn update(&mut self, smsh: &Shell) {
+        self.builtins = smsh.builtins.keys().cloned().collect();
+
+        self.functions = smsh
+            .scopes
+            .function_names()
+            .filter_map(|x| smsh.scopes.get_function(x))
+            .cloned()
+            .collect();
+
+        self.variables = smsh
+            .scopes
+            .variables()
+            .map(|(key, val)| (key.to_string(), val.to_string()))
+            .collect();
+
+        self.modules = module_names(smsh);
+        self.completions = smsh.completions.clone();
+        self.function_words.clear();
+    }
+}
@@ -0,0 +1,78 @@
+// Each prompt is given by the user function or variable of its name,
+// expanded like any other line; failing that, a default is used.
+
+use crossterm::style::Stylize;
+use nix::unistd;
+
+use super::Shell;
+use crate::line::expand_text;
+use crate::sources::PromptText;
+
+use std::env::current_dir;
+
+pub fn render_prompt(smsh: &mut Shell) -> PromptText {
+    PromptText {
+        left: render(smsh, "prompt").unwrap_or_else(default_prompt),
+        right: render(smsh, "prompt_right").unwrap_or_default(),
+        multiline: render(smsh, "prompt_multiline").unwrap_or_else(|| "> ".to_string()),
+    }
+}
+
+// A function's output is used as is, less trailing newlines.  A variable
+// is expanded into words joined by single spaces; its trailing whitespace
+// is kept.
+fn render(smsh: &mut Shell, name: &str) -> Option<String> {
+    let text = if smsh.get_user_function(name).is_some() {
+        format!("\"!{{{}}}\"", name)
+    } else {
+        smsh.get_user_variable(name)?
+    };
+
+    match expand_text(&text, smsh) {
+        Ok(prompt) => {
+            let mut prompt = prompt.trim_end_matches('\n').to_string();
+            prompt.push_str(&text[text.trim_end().len()..]);
+            Some(prompt)
+        }
+        Err(e) => {
+            eprintln!("smsh: {}: {}", name, e);
+            None
+        }
+    }
+}
+
+pub fn default_prompt() -> String {
+    let mut prompt_string = String::new();
+
+    if let Ok(path) = current_dir() {
+        if let Some(s) = path.to_str() {
+            prompt_string.push_str(s);
+        }
+    }
+
+    if unistd::getuid().is_root() {
+        prompt_string.push_str("# ");
+        prompt_string = prompt_string.red().to_string();
+    } else {
+        prompt_string.push_str("$ ");
+    };
+
+    prompt_string
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_1() {
+        let mut smsh = Shell::new(false);
+        let text = "t{red}t{prompt_char}t{reset} ".to_string();
+        smsh.insert_user_variable("prompt".to_string(), text, false);
+
+        let prompt_char = if unistd::getuid().is_root() { "#" } else { "$" };
+        let expected = format!("\x1b[31m{}\x1b[0m ", prompt_char);
+
+        assert_eq!(Some(expected), render(&mut smsh, "prompt"));
+    }
+}
@@ -0,0 +1,149 @@
+// See doc/scopes.md.  A new scope is pushed each time a user function is
+// invoked; lookups search from the innermost scope out to the root.
+// Positional arguments belong to the innermost scope alone.
+
+use std::collections::HashMap;
+
+use crate::sources::user_function::UserFunction;
+
+#[derive(Default)]
+struct Scope {
+    variables: HashMap<String, String>,
+    functions: HashMap<String, UserFunction>,
+    arguments: Vec<String>, // {0}, {1}, ...
+}
+
+pub struct Scopes {
+    scopes: Vec<Scope>, // Never empty; the first is the root scope
+}
+
+impl Scopes {
+    pub fn new() -> Self {
+        let root = Scope {
+            arguments: vec!["smsh".to_string()],
+            ..Default::default()
+        };
+
+        Scopes { scopes: vec![root] }
+    }
+
+    pub fn push(&mut self, arguments: Vec<String>) {
+        self.scopes.push(Scope {
+            arguments,
+            ..Default::default()
+        });
+    }
+
+    // The root scope is never popped
+    pub fn pop(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    fn scope(&mut self, global: bool) -> &mut Scope {
+        if global {
+            self.scopes.first_mut().unwrap()
+        } else {
+            self.scopes.last_mut().unwrap()
+        }
+    }
+
+    pub fn arguments(&self) -> &[String] {
+        &self.scopes.last().unwrap().arguments
+    }
+
+    pub fn insert_variable(&mut self, key: String, val: String, global: bool) {
+        self.scope(global).variables.insert(key, val);
+    }
+
+    pub fn get_variable(&self, key: &str) -> Option<&String> {
+        self.scopes.iter().rev().find_map(|x| x.variables.get(key))
+    }
+
+    pub fn insert_function(&mut self, func: UserFunction, global: bool) {
+        let name = func.name().to_string();
+        self.scope(global).functions.insert(name, func);
+    }
+
+    pub fn get_function(&self, name: &str) -> Option<&UserFunction> {
+        self.scopes.iter().rev().find_map(|x| x.functions.get(name))
+    }
+
+    pub fn remove_function(&mut self, name: &str, global: bool) -> Option<UserFunction> {
+        self.scope(global).functions.remove(name)
+    }
+
+    // Removes every function of the current scope
+    pub fn take_functions(&mut self) -> Vec<UserFunction> {
+        self.scope(false)
+            .functions
+            .drain()
+            .map(|(_, x)| x)
+            .collect()
+    }
+
+    // Names of the functions visible from the current scope
+    pub fn function_names(&self) -> impl Iterator<Item = &str> {
+        self.scopes
+            .iter()
+            .flat_map(|x| x.functions.keys())
+            .map(|x| x.as_str())
+    }
+
+    // Likewise, the variables, with their values.  A variable shadowed
+    // by an inner scope is given once for each scope.
+    pub fn variables(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.scopes
+            .iter()
+            .flat_map(|x| x.variables.iter())
+            .map(|(key, val)| (key.as_str(), val.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scopes_1() {
+        let mut scopes = Scopes::new();
+        scopes.insert_variable("x".to_string(), "root".to_string(), false);
+        scopes.push(vec![]);
+        scopes.insert_variable("x".to_string(), "inner".to_string(), false);
+        assert_eq!(Some(&"inner".to_string()), scopes.get_variable("x"));
+        scopes.pop();
+        assert_eq!(Some(&"root".to_string()), scopes.get_variable("x"));
+    }
+
+    #[test]
+    fn scopes_2() {
+        let mut scopes = Scopes::new();
+        scopes.push(vec![]);
+        scopes.insert_variable("x".to_string(), "global".to_string(), true);
+        scopes.pop();
+        scopes.pop(); // Root scope remains
+        assert_eq!(Some(&"global".to_string()), scopes.get_variable("x"));
+    }
+
+    #[test]
+    fn scopes_3() {
+        let mut scopes = Scopes::new();
+        let func = UserFunction::new("f".to_string(), vec![]);
+        scopes.insert_function(func, true);
+        scopes.push(vec![]);
+        assert!(scopes.get_function("f").is_some());
+        assert!(scopes.remove_function("f", false).is_none());
+        assert!(scopes.remove_function("f", true).is_some());
+        assert!(scopes.get_function("f").is_none());
+    }
+
+    #[test]
+    fn scopes_4() {
+        let mut scopes = Scopes::new();
+        scopes.push(vec!["f".to_string(), "one".to_string()]);
+        assert_eq!(["f", "one"], scopes.arguments());
+        scopes.pop();
+        assert_eq!(["smsh"], scopes.arguments());
+    }
+}
@@ -0,0 +1,64 @@
+// The interactive shell ignores the signals a terminal sends on behalf of
+// the user, leaving them to the foreground job.  Forked children restore the
+// default dispositions, since ignored signals survive `execvp`.
+
+use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::sys::wait::WaitStatus;
+
+const TERMINAL_SIGNALS: [Signal; 4] = [
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTSTP,
+    Signal::SIGTTOU, // Lets a background shell take back the terminal
+];
+
+pub fn ignore_terminal_signals() {
+    set_terminal_signals(SigHandler::SigIgn);
+}
+
+pub fn restore_default_signals() {
+    set_terminal_signals(SigHandler::SigDfl);
+}
+
+fn set_terminal_signals(handler: SigHandler) {
+    for sig in TERMINAL_SIGNALS {
+        // Neither handler runs any code within the shell
+        unsafe {
+            let _ = signal(sig, handler);
+        }
+    }
+}
+
+// Maps a terminated child's status to a return value: its exit
+// status, or 128 + n if it was killed by signal n
+pub fn exit_status(status: WaitStatus) -> Option<i32> {
+    match status {
+        WaitStatus::Exited(_pid, rv) => Some(rv),
+        WaitStatus::Signaled(_pid, sig, _core_dumped) => Some(128 + sig as i32),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nix::unistd::Pid;
+
+    #[test]
+    fn exit_status_1() {
+        let status = WaitStatus::Exited(Pid::from_raw(100), 3);
+        assert_eq!(Some(3), exit_status(status));
+    }
+
+    #[test]
+    fn exit_status_2() {
+        let status = WaitStatus::Signaled(Pid::from_raw(100), Signal::SIGINT, false);
+        assert_eq!(Some(130), exit_status(status));
+    }
+
+    #[test]
+    fn exit_status_3() {
+        let status = WaitStatus::Stopped(Pid::from_raw(100), Signal::SIGTSTP);
+        assert_eq!(None, exit_status(status));
+    }
+}
@@ -1,15 +1,34 @@
+use nix::unistd::Pid;
+
+use std::env;
+use std::path::Path;
+
 pub struct State {
     interactive: bool,
+    name: String, // As invoked, less any leading directories
+    last_background_pid: Option<Pid>,
+    loading_module: Option<String>, // A script module, while its script runs
 
     // Each builtin and each external command sets (resets) this.
-    // Shell constructs do not affect this.
+    // Shell constructs do not affect this.  A command killed by
+    // signal n sets this to 128 + n.
     pub rv: i32,
 }
 
 impl State {
-    pub fn new() -> Self {
+    pub fn new(interactive: bool) -> Self {
+        let name = env::args()
+            .next()
+            .as_deref()
+            .and_then(|x| Path::new(x).file_name())
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_else(|| "smsh".to_string());
+
         State {
-            interactive: true,
+            interactive,
+            name,
+            last_background_pid: None,
+            loading_module: None,
             rv: 0,
         }
     }
@@ -17,4 +36,25 @@ impl State {
     pub fn is_interactive(&self) -> bool {
         self.interactive
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn last_background_pid(&self) -> Option<Pid> {
+        self.last_background_pid
+    }
+
+    pub fn set_last_background_pid(&mut self, pid: Pid) {
+        self.last_background_pid = Some(pid);
+    }
+
+    pub fn loading_module(&self) -> Option<&str> {
+        self.loading_module.as_deref()
+    }
+
+    // Yields the module that was being loaded before, if any
+    pub fn set_loading_module(&mut self, name: Option<String>) -> Option<String> {
+        std::mem::replace(&mut self.loading_module, name)
+    }
 }
@@ -0,0 +1,84 @@
+// Special variables are computed by the shell upon expansion, and
+// cannot be assigned to by `let`.  See doc/expansion.md.
+
+use nix::unistd::{getpid, getppid};
+
+use super::Shell;
+
+use std::env;
+
+type Getter = fn(&Shell) -> String;
+
+const SPECIAL_VARIABLES: [(&str, Getter); 7] = [
+    ("rv", |smsh| smsh.rv().to_string()),
+    ("pid", |_| getpid().to_string()),
+    ("ppid", |_| getppid().to_string()),
+    ("cwd", |_| match env::current_dir() {
+        Ok(path) => path.to_string_lossy().to_string(),
+        Err(_) => String::new(),
+    }),
+    ("bgpid", |smsh| match smsh.state().last_background_pid() {
+        Some(pid) => pid.to_string(),
+        None => String::new(),
+    }),
+    ("shell", |smsh| smsh.state().name().to_string()),
+    ("interactive", |smsh| smsh.is_interactive().to_string()),
+];
+
+pub fn get_special_variable(smsh: &Shell, key: &str) -> Option<String> {
+    SPECIAL_VARIABLES
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, getter)| getter(smsh))
+}
+
+pub fn special_variable_names() -> impl Iterator<Item = &'static str> {
+    SPECIAL_VARIABLES.iter().map(|(name, _)| *name)
+}
+
+// Positional arguments are special, too
+pub fn is_special_variable(key: &str) -> bool {
+    SPECIAL_VARIABLES.iter().any(|(name, _)| *name == key) || get_positional(key).is_some()
+}
+
+// Maps `n` to (n, false), and `n..` to (n, true)
+pub fn get_positional(key: &str) -> Option<(usize, bool)> {
+    let (n, rest) = match key.strip_suffix("..") {
+        Some(n) => (n, true),
+        None => (key, false),
+    };
+
+    if n.is_empty() || !n.chars().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+
+    n.parse::<usize>().ok().map(|n| (n, rest))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn get_positional_1() {
+        assert_eq!(Some((0, false)), get_positional("0"));
+        assert_eq!(Some((12, false)), get_positional("12"));
+        assert_eq!(Some((1, true)), get_positional("1.."));
+    }
+
+    #[test]
+    fn get_positional_2() {
+        for key in ["", "..", "x", "1x", "1..2", "-1"] {
+            assert_eq!(None, get_positional(key));
+        }
+    }
+
+    #[test]
+    fn is_special_variable_1() {
+        for key in ["rv", "pid", "cwd", "0", "1.."] {
+            assert!(is_special_variable(key));
+        }
+
+        assert!(!is_special_variable("myvar"));
+    }
+}
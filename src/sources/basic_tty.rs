@@ -1,103 +1,76 @@
-// This file is a holdover from early development days.
-// It provides an infallible means of reading
-// from STDIN.
+// Reads logical lines from input that is not a terminal, such as
+// a pipe, a redirected file or the text given by `smsh -c`.
+// Nobody is prompted.
 
-use anyhow::Result;
-use nix::unistd::getuid;
+use anyhow::{anyhow, Result};
+use nix::unistd::read;
 
-use std::io::{self, Stdin, Write};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::io::RawFd;
 
-use super::{Source, SourceKind, is_complete};
+use super::{is_complete, Source, SourceKind};
 use crate::line::Line;
 
 pub struct BasicTty {
-    stdin: Stdin,
+    input: Box<dyn BufRead>,
+    source_kind: SourceKind,
     line_num: usize,
-    last_line: Option<Line>
+    last_line: Option<Line>,
 }
 
 impl BasicTty {
-    pub fn build_source() -> Box<dyn Source> {
-        let stdin = io::stdin();
-
-        Box::new(Tty { stdin, line_num: 0, last_line: None})
+    pub fn build_source(input: Box<dyn BufRead>, source_kind: SourceKind) -> Box<dyn Source> {
+        Box::new(BasicTty {
+            input,
+            source_kind,
+            line_num: 0,
+            last_line: None,
+        })
     }
 
-    // Used to complete logical lines when they transcend physical lines
-    fn get_secondary_line(&mut self) -> Result<Option<String>> {
+    // Yields one physical line, less its newline
+    fn read_physical_line(&mut self) -> Result<Option<String>> {
         let mut buffer = String::new();
 
-        print!("> ");
-        io::stdout().flush()?;
-
-        let num_bytes_read = self.stdin.read_line(&mut buffer)?;
-
-        if num_bytes_read == 0 {
-            Ok(None)
-        } else {
-            Ok(Some(buffer))
+        if self.input.read_line(&mut buffer)? == 0 {
+            return Ok(None); // EOF was found
         }
-    }
 
-    fn simple_prompt() -> String {
-        if getuid().is_root() {
-            "# ".to_string()
-        } else {
-            "$ ".to_string()
+        while buffer.ends_with('\n') || buffer.ends_with('\r') {
+            buffer.pop();
         }
+
+        self.line_num += 1;
+
+        Ok(Some(buffer))
     }
 }
 
 impl Source for BasicTty {
-    fn get_line(&mut self, prompt: Prompt) -> Result<Option<Line>> {
-        let prompt = match prompt {
-            Prompt::MainLoop => {
-                Tty::simple_prompt()
-            }
-            Prompt::Block => {
-                "> ".to_string()
-            }
+    fn get_line(&mut self) -> Result<Option<Line>> {
+        let mut buffer = match self.read_physical_line()? {
+            Some(buffer) => buffer,
+            None => return Ok(None),
         };
 
-        let mut buffer = String::new();
-
-        print!("{}", prompt);
-        io::stdout().flush()?;
+        let line_num = self.line_num;
 
-        // `buffer` should not contain trailing newline 
-        let num_bytes_read = self.stdin.read_line(&mut buffer)?;
-        while buffer.ends_with('\n') {
-            buffer.pop();
-        }
-
-        if num_bytes_read == 0 {
-            Ok(None) // EOF was found
-        } else {
-            self.line_num += 1;
-
-            while !is_complete(buffer.as_str()) {
-                buffer.push('\n');
-                match self.get_secondary_line()? {
-                    Some(addendum) => {
-                        buffer.push_str(addendum.as_str());
-                    }
-                    None => {
-                        eprintln!("smsh: Unexpected EOF");
-                        return Ok(Some(Line::new("".to_string(), self.line_num, SourceKind::Tty)?));
-                    }
-                }
+        while !is_complete(buffer.as_str()) {
+            match self.read_physical_line()? {
+                Some(addendum) => join_lines(&mut buffer, &addendum),
+                None => return Err(anyhow!("Unexpected end of input")),
             }
+        }
 
-            let line = Line::new(buffer, self.line_num, SourceKind::Tty)?;
+        let line = Line::new(buffer, line_num, self.source_kind.clone())?;
 
-            self.last_line = Some(line.clone());
+        self.last_line = Some(line.clone());
 
-            Ok(Some(line))
-        }
+        Ok(Some(line))
     }
 
-    fn is_tty(&self) -> bool {
-        true
+    fn get_source_kind(&self) -> SourceKind {
+        self.source_kind.clone()
     }
 
     fn print_error(&mut self) -> Result<()> {
@@ -108,3 +81,92 @@ impl Source for BasicTty {
         Ok(())
     }
 }
+
+// Reads a file descriptor a byte at a time, so that nothing past the line
+// being read is taken from it.  Commands run from standard input then
+// find the rest of it where the shell left off.
+pub struct Unbuffered(RawFd);
+
+impl Unbuffered {
+    pub fn build_input(fd: RawFd) -> Box<dyn BufRead> {
+        Box::new(BufReader::with_capacity(1, Unbuffered(fd)))
+    }
+}
+
+impl Read for Unbuffered {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(1);
+        Ok(read(self.0, &mut buf[..len])?)
+    }
+}
+
+// Appends the physical line `next` to the incomplete `buffer`.  An escaped
+// newline is removed; a newline within quotes is kept, and one following
+// a pipe becomes a space.
+fn join_lines(buffer: &mut String, next: &str) {
+    if buffer.ends_with('\\') && is_complete(&buffer[..buffer.len() - 1]) {
+        buffer.pop();
+    } else if is_complete(&format!("{} x", buffer)) {
+        buffer.push(' ');
+    } else {
+        buffer.push('\n');
+    }
+
+    buffer.push_str(next);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn basic_tty_1() {
+        let input = Box::new(Cursor::new("echo one |\n  cat\necho 'two\nthree'\n"));
+        let mut source = BasicTty::build_source(input, SourceKind::Command);
+
+        let first = source.get_line().unwrap().unwrap();
+        assert_eq!("echo one |   cat", first.raw_text());
+        assert_eq!(1, first.identifier().line_num);
+
+        let second = source.get_line().unwrap().unwrap();
+        assert_eq!("echo 'two\nthree'", second.raw_text());
+        assert_eq!(3, second.identifier().line_num);
+
+        assert!(source.get_line().unwrap().is_none());
+    }
+
+    #[test]
+    fn join_lines_1() {
+        let mut buffer = "echo one \\".to_string();
+        join_lines(&mut buffer, "two");
+        assert_eq!("echo one two", buffer);
+    }
+
+    #[test]
+    fn basic_tty_2() {
+        let input = Box::new(Cursor::new("echo 'unterminated\n"));
+        let mut source = BasicTty::build_source(input, SourceKind::Stdin);
+
+        assert!(source.get_line().is_err());
+    }
+
+    #[test]
+    fn unbuffered_1() {
+        use nix::unistd::{close, pipe, write};
+
+        let (rd, wr) = pipe().unwrap();
+        write(wr, b"echo one\nrest\n").unwrap();
+        close(wr).unwrap();
+
+        let mut source = BasicTty::build_source(Unbuffered::build_input(rd), SourceKind::Stdin);
+        assert_eq!("echo one", source.get_line().unwrap().unwrap().raw_text());
+
+        // What follows the line is left unread
+        let mut buf = [0u8; 16];
+        let len = read(rd, &mut buf).unwrap();
+        assert_eq!(b"rest\n", &buf[..len]);
+
+        close(rd).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use super::{Source, SourceKind};
+use super::{Interrupt, Source, SourceKind};
 use crate::line::{Line, LineID};
 
 #[derive(Clone)]
@@ -75,11 +75,20 @@ impl Source for For {
         }
     }
 
-    fn get_source_kind(&self) -> SourceKind{
+    fn get_source_kind(&self) -> SourceKind {
         self.for_line_identifier.source_kind.clone()
     }
 
     fn print_error(&mut self) -> Result<()> {
         Ok(())
     }
+
+    fn interrupt(&mut self, interrupt: Interrupt) -> bool {
+        if interrupt == Interrupt::Break {
+            self.iter_idx = self.iterator_values.len();
+        }
+
+        self.line_num = 0;
+        true
+    }
 }
@@ -3,19 +3,20 @@
 //      be neater
 //
 //      Sources::sources could be a vec of tuples (Box<dyn Source>, SourceKind)
-use std::collections::VecDeque;
-
 use unicode_segmentation::UnicodeSegmentation;
 
 use anyhow::Result;
 
 use super::line::Line;
+use subshell::SubshellSource;
 
+pub mod basic_tty;
 pub mod r#for;
 pub mod script;
 pub mod subshell;
 pub mod tty;
 pub mod user_function;
+pub mod r#while;
 
 // Used in Line struct to identify source
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -24,96 +25,153 @@ pub enum SourceKind {
     Subshell,
     UserFunction(String), // String contains function name
     Script(String),       // String contains script pathname
+    Stdin,                // Standard input, when not a terminal
+    Command,              // Given by `smsh -c`
+}
+
+// Rendered by the shell each time the user is about to be prompted
+pub struct PromptText {
+    pub left: String,
+    pub right: String,
+    pub multiline: String, // Indicates continuation lines
+}
+
+// Given to the innermost loop by `break` and `continue`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interrupt {
+    Break,
+    Continue,
 }
 
 pub trait Source {
     fn get_line(&mut self) -> Result<Option<Line>>;
     fn get_source_kind(&self) -> SourceKind;
     fn print_error(&mut self) -> Result<()>;
+
+    // True if the next call to get_line will prompt the user
+    fn will_prompt(&self) -> bool {
+        false
+    }
+
+    // Used by sources that prompt the user
+    fn set_prompt(&mut self, _prompt: PromptText) {}
+
+    // A source with arguments runs within a scope of its own,
+    // to which they are bound as {0}, {1}, ...
+    fn arguments(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    // A loop about to begin a pass gives the condition the pass depends
+    // on.  The shell tests it, and discards the loop if it is false.
+    fn condition(&mut self) -> Option<String> {
+        None
+    }
+
+    // Loops take `break` and `continue`; other sources pass them by
+    fn interrupt(&mut self, _interrupt: Interrupt) -> bool {
+        false
+    }
 }
 
 pub struct Sources {
     sources: Vec<Box<dyn Source>>,
-    buffer: VecDeque<Line>, // Only contains complete lines
+    floors: Vec<usize>, // Sources beneath the topmost floor are not visible
 }
 
 impl Sources {
     pub fn new() -> Self {
         Sources {
             sources: vec![],
-            buffer: VecDeque::<Line>::new(),
+            floors: vec![],
         }
     }
 
-    pub fn get_line(&mut self) -> Result<Option<Line>> {
-        if let Some(line) = self.buffer.pop_front() {
-            Ok(Some(line))
-        } else if let Some(mut source) = self.sources.pop() {
-            match source.get_line() {
-                Ok(Some(line)) => {
-                    self.sources.push(source);
-                    Ok(Some(line))
-                }
-                Ok(None) => self.get_line(),
-                Err(e) => {
-                    self.sources.push(source);
-                    Err(e)
-                }
-            }
+    fn floor(&self) -> usize {
+        self.floors.last().copied().unwrap_or(0)
+    }
+
+    // Topmost visible source
+    pub fn top(&mut self) -> Option<&mut Box<dyn Source>> {
+        if self.sources.len() > self.floor() {
+            self.sources.last_mut()
         } else {
-            Ok(None)
+            None
         }
     }
 
-    // Captures one block from a single source
-    pub fn get_block(&mut self, source_kind: &SourceKind, indent: usize) -> Result<Vec<Line>> {
-        let mut lines = Vec::<Line>::new();
-
-        if !self.buffer.is_empty() {
-            while let Some(line) = self.buffer.pop_front() {
-                if *line.source() == *source_kind && line.indentation() >= indent {
-                    lines.push(line);
-                } else {
-                    self.buffer.push_front(line);
-                    break;
-                }
-            }
-        } else if let Some(mut source) = self.sources.pop() {
-            while let Some(line) = source.get_line()? {
-                if *line.source() == *source_kind && line.indentation() >= indent {
-                    lines.push(line);
-                } else {
-                    self.sources.push(source);
-                    self.buffer.push_front(line);
-                    break;
-                }
-            }
+    // Discards the topmost source, once exhausted
+    pub fn pop(&mut self) -> Option<Box<dyn Source>> {
+        if self.sources.len() > self.floor() {
+            self.sources.pop()
+        } else {
+            None
         }
-
-        Ok(lines)
     }
 
-    // Pushes block of lines onto buffer.
+    // Pushes block of lines onto the execution stack.
     // Order is preserved.
     pub fn push_block(&mut self, lines: Vec<Line>) {
-        for line in lines {
-            self.buffer.push_back(line);
-        }
+        self.sources.push(SubshellSource::build_source(lines));
     }
 
     pub fn push_line(&mut self, line: Line) {
-        self.buffer.push_front(line);
+        self.push_block(vec![line]);
     }
 
     pub fn push_source(&mut self, source: Box<dyn Source>) {
         self.sources.push(source)
     }
 
+    // Hides every source currently on the stack
+    pub fn push_floor(&mut self) {
+        self.floors.push(self.sources.len());
+    }
+
+    pub fn pop_floor(&mut self) {
+        self.floors.pop();
+    }
+
+    // Gives `interrupt` to the innermost loop, discarding the sources
+    // above it.  A loop beyond a source with a scope of its own, such as
+    // a user function, is not reached.
+    pub fn interrupt(&mut self, interrupt: Interrupt) -> bool {
+        for i in (self.floor()..self.sources.len()).rev() {
+            if self.sources[i].interrupt(interrupt) {
+                self.sources.truncate(i + 1);
+                return true;
+            }
+
+            if self.sources[i].arguments().is_some() {
+                break;
+            }
+        }
+
+        false
+    }
+
+    // Discards the visible sources, returning the number that had scopes
+    pub fn discard(&mut self) -> usize {
+        let mut scopes = 0;
+
+        while let Some(source) = self.pop() {
+            if source.arguments().is_some() {
+                scopes += 1;
+            }
+        }
+
+        scopes
+    }
+
     pub fn clear(&mut self) {
         self.sources.clear();
+        self.floors.clear();
     }
 
-    pub fn backtrace(&mut self) {
+    // Returns the number of discarded sources that had scopes
+    pub fn backtrace(&mut self) -> usize {
+        let mut scopes = 0;
+
         while let Some(mut source) = self.sources.pop() {
             if source.get_source_kind() == SourceKind::Tty {
                 self.sources.push(source);
@@ -121,7 +179,13 @@ impl Sources {
             } else {
                 let _ = source.print_error();
             }
+
+            if source.arguments().is_some() {
+                scopes += 1;
+            }
         }
+
+        scopes
     }
 }
 
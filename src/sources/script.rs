@@ -12,6 +12,7 @@ pub struct Script {
     body: Vec<String>,
     line_num: usize,
     last_line: Option<Line>,
+    arguments: Option<Vec<String>>, // A script given arguments runs in a scope of its own
 }
 
 impl Script {
@@ -19,17 +20,23 @@ impl Script {
     // dolling it out; should probably read a (logical) line at a time.
     // On the other hand, though, even a large script (10000 lines) is
     // less than 10 MB, so...
-    pub fn build_source(path: PathBuf) -> Result<Box<dyn Source>> {
-        let body = read_to_string(&path)?
+    pub fn build_source(path: PathBuf, arguments: Option<Vec<String>>) -> Result<Box<dyn Source>> {
+        let mut body: Vec<String> = read_to_string(&path)?
             .lines()
             .map(|x| x.to_string())
             .collect();
 
+        // A `#!` line is blanked, so that line numbers stay true
+        if body.first().map(|x| x.starts_with("#!")).unwrap_or(false) {
+            body[0].clear();
+        }
+
         let script = Script {
             path,
             body,
             line_num: 0,
             last_line: None,
+            arguments,
         };
 
         Ok(Box::new(script))
@@ -58,8 +65,8 @@ impl Source for Script {
 
     fn get_source_kind(&self) -> SourceKind {
         let p = match self.path.to_str() {
-            Some(p) => { p.to_string() }
-            None  => { "".to_string() }
+            Some(p) => p.to_string(),
+            None => "".to_string(),
         };
 
         SourceKind::Script(p)
@@ -72,4 +79,8 @@ impl Source for Script {
 
         Ok(())
     }
+
+    fn arguments(&self) -> Option<Vec<String>> {
+        self.arguments.clone()
+    }
 }
@@ -1,37 +1,448 @@
+// Tab completion.  The word under the cursor is completed according to
+// where it falls on the line:  commands in command position, variable
+// names within `{` and `e{`, module names after `self::load_module` and
+// `self::unload_module`, and the arguments of a command by its completion
+// rules, if it has any, or as paths.  External commands and paths are
+// looked up as the line is completed; everything else is taken from the
+// names the shell keeps for its terminal.
+
+use reedline::{Completer, Span, Suggestion};
+
+use crate::line::{get_tokens, Quote, Token};
+use crate::shell::completion::{function_words, Argument};
+use crate::shell::names::{Names, SharedNames};
+use crate::shell::path_commands;
+use crate::shell::variables::special_variable_names;
+
 use std::env;
-use std::path::PathBuf;
+use std::path::Path;
 
-use anyhow::Result;
-use reedline::DefaultCompleter;
+pub struct SmshCompleter {
+    names: SharedNames,
+}
 
-pub fn build_command_completer() -> Result<DefaultCompleter> {
-    let commands = get_commands()?;
+impl SmshCompleter {
+    pub fn new(names: SharedNames) -> Self {
+        SmshCompleter { names }
+    }
+}
 
-    let completer = DefaultCompleter::new(commands);
+#[derive(PartialEq, Eq, Debug)]
+enum Context {
+    Command,
+    Variable,
+    Environment,
+    Module { loaded: bool },
+    Argument { quote: Quote, words: Vec<String> }, // Words of its command so far
+}
 
-    Ok(completer)
+// The word being completed, from its start up to the cursor
+#[derive(PartialEq, Eq, Debug)]
+struct Word {
+    context: Context,
+    start: usize, // Of the text to be replaced, within the line
+    text: String, // That is to be completed
 }
 
-// TODO:  Remove 'unwrap' call,
-// Need a way to update this if a new command is installed
-fn get_commands() -> Result<Vec<String>> {
-    let paths = if let Some(os_string) = env::var_os("PATH") {
-        let string = os_string.into_string().unwrap();
-        string.split(":").map(|x| x.to_string()).collect()
-    } else {
+impl Completer for SmshCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        // Only the logical line holding the cursor matters
+        let offset = line[..pos].rfind('\n').map(|x| x + 1).unwrap_or(0);
+
+        let word = match get_word(&line[offset..pos]) {
+            Some(word) => word,
+            None => return vec![],
+        };
+
+        let mut names = self.names.lock();
+        let names = &mut *names;
+
+        let mut values = match word.context {
+            Context::Command if word.text.contains('/') => {
+                complete_path(&word.text, Quote::Unquoted, false)
+            }
+            Context::Command => complete_command(&word.text, names),
+            Context::Variable => {
+                let variables = names
+                    .variables
+                    .iter()
+                    .map(|(x, _)| x.clone())
+                    .chain(special_variable_names().map(String::from));
+                complete_name(&word.text, variables, "}")
+            }
+            Context::Environment => {
+                let variables = env::vars_os().filter_map(|(x, _)| x.into_string().ok());
+                complete_name(&word.text, variables, "}")
+            }
+            Context::Module { loaded } => {
+                let modules = names
+                    .modules
+                    .iter()
+                    .filter(|(_, x)| *x == loaded)
+                    .map(|(x, _)| x.clone());
+                complete_name(&word.text, modules, " ")
+            }
+            Context::Argument { quote, words } => {
+                complete_argument(&word.text, quote, &words, names)
+            }
+        };
+
+        values.sort();
+        values.dedup();
+
+        let span = Span {
+            start: offset + word.start,
+            end: pos,
+        };
+
+        values
+            .into_iter()
+            .map(|value| Suggestion {
+                value,
+                description: None,
+                extra: None,
+                span,
+                append_whitespace: false,
+            })
+            .collect()
+    }
+}
+
+// Builtins, functions and the executables on PATH
+fn complete_command(text: &str, names: &Names) -> Vec<String> {
+    let commands = names
+        .builtins
+        .iter()
+        .cloned()
+        .chain(names.functions.iter().map(|x| x.name().to_string()))
+        .chain(path_commands());
+
+    complete_name(text, commands, " ")
+}
+
+// By the rule for the command and subcommands in `words`.  Flags are
+// offered for a word beginning with `-`, and subcommands until an
+// argument is given; arguments are completed by the rule's kind of
+// argument.  Paths are offered where there is no rule.
+fn complete_argument(text: &str, quote: Quote, words: &[String], names: &mut Names) -> Vec<String> {
+    let words: Vec<&str> = words.iter().map(|x| x.as_str()).collect();
+
+    let position = match names.completions.position(&words) {
+        Some(position) => position,
+        None => return complete_path(text, quote, false),
+    };
+
+    let rule = position.rule;
+
+    if text.starts_with('-') {
+        return complete_name(text, rule.flags.iter().cloned(), " ");
+    }
+
+    let mut values = if position.after_argument {
         vec![]
+    } else {
+        complete_name(text, rule.subcommands.iter().cloned(), " ")
     };
 
-    let mut commands: Vec<String> = vec![];
+    match &rule.argument {
+        Some(Argument::File) => values.extend(complete_path(text, quote, false)),
+        Some(Argument::Directory) => values.extend(complete_path(text, quote, true)),
+        Some(Argument::Words(x)) => values.extend(complete_name(text, x.iter().cloned(), " ")),
+        Some(Argument::Function(function)) => {
+            let arguments: Vec<String> = words[1..].iter().map(|x| x.to_string()).collect();
+            let key = (function.clone(), arguments.clone());
 
-    for p in paths {
-        let path = PathBuf::from(p);
-        for entry in path.read_dir()? {
-            if let Ok(entry) = entry {
-                commands.push(entry.file_name().into_string().unwrap_or("".to_string()));
-            }
+            let output = names.function_words.entry(key).or_insert_with(|| {
+                function_words(function, arguments, &names.functions, &names.variables)
+            });
+            values.extend(complete_name(text, output.iter().cloned(), " "));
         }
+        // A command with subcommands takes no arguments of its own, unless told
+        None if rule.subcommands.is_empty() => values.extend(complete_path(text, quote, false)),
+        None => {}
     }
 
-    Ok(commands)
+    values
+}
+
+fn complete_name(text: &str, names: impl Iterator<Item = String>, suffix: &str) -> Vec<String> {
+    names
+        .filter(|x| x.starts_with(text))
+        .map(|x| x + suffix)
+        .collect()
+}
+
+// Directories are completed with a trailing slash, so that completion
+// may continue within them, and files with a space.  Names needing
+// quotes are given them.
+fn complete_path(text: &str, quote: Quote, directories_only: bool) -> Vec<String> {
+    let (dir, file) = match text.rfind('/') {
+        Some(i) => (&text[..=i], &text[i + 1..]),
+        None => ("", text),
+    };
+
+    let entries = match Path::new(if dir.is_empty() { "." } else { dir }).read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut values = Vec::<String>::new();
+
+    for entry in entries.flatten() {
+        // A name that is not UTF-8 cannot be typed
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+
+        if !name.starts_with(file) || (name.starts_with('.') && !file.starts_with('.')) {
+            continue;
+        }
+
+        let path = format!("{}{}", dir, name);
+
+        if entry.path().is_dir() {
+            values.push(quote_path(path + "/", quote));
+        } else if !directories_only {
+            values.push(quote_path(path, quote) + " ");
+        }
+    }
+
+    values
+}
+
+fn quote_path(path: String, quote: Quote) -> String {
+    let quote = match quote {
+        Quote::Unquoted if path.contains(needs_quotes) => Quote::SingleQuoted,
+        quote => quote,
+    };
+
+    match quote {
+        Quote::SingleQuoted => format!("'{}'", path),
+        Quote::DoubleQuoted => format!("\"{}\"", path),
+        Quote::Unquoted => path,
+    }
+}
+
+fn needs_quotes(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '\'' | '"' | '\\' | '{' | '}')
+}
+
+// Decides what the word ending at the end of `line` is, and so how it is
+// completed.  A word left unfinished, as in `{na` or `'some fi`, is closed
+// first, so that the line may be tokenized.
+fn get_word(line: &str) -> Option<Word> {
+    let tokens = ["", "}", "'", "\""]
+        .iter()
+        .find_map(|x| get_tokens(&format!("{}{}", line, x)).ok())?;
+
+    // A token ending before the cursor is done with; a new word begins
+    let (previous, current) = match tokens.split_last() {
+        Some((last, rest)) if last.span().end >= line.len() => (rest, Some(last)),
+        _ => (tokens.as_slice(), None),
+    };
+
+    let (start, raw) = match current {
+        Some(token) => (token.span().start, &line[token.span().start..]),
+        None => (line.len(), ""),
+    };
+
+    let word = |context, skip: usize, text: &str| {
+        Some(Word {
+            context,
+            start: start + skip,
+            text: text.to_string(),
+        })
+    };
+
+    if let Some(name) = raw.strip_prefix("e{") {
+        return word(Context::Environment, 2, name);
+    } else if let Some(name) = raw.strip_prefix('{') {
+        return word(Context::Variable, 1, name);
+    }
+
+    let words = command_words(previous);
+
+    match words.first().map(|x| x.as_str()) {
+        None => return word(Context::Command, 0, raw),
+        Some("self::load_module") if words.len() == 1 => {
+            return word(Context::Module { loaded: false }, 0, raw);
+        }
+        Some("self::unload_module") if words.len() == 1 => {
+            return word(Context::Module { loaded: true }, 0, raw);
+        }
+        _ => {}
+    }
+
+    // The whole of a quoted word is replaced, quotes and all
+    match current {
+        Some(token) if token.quote() != Quote::Unquoted => {
+            let quote = token.quote();
+            word(Context::Argument { quote, words }, 0, token.text())
+        }
+        _ => {
+            let quote = Quote::Unquoted;
+            word(Context::Argument { quote, words }, 0, raw)
+        }
+    }
+}
+
+// The words of the command being typed:  those since the start of the
+// line or of a conditional, or since the last `|`, `and`, `or` or `not`
+fn command_words(previous: &[Token]) -> Vec<String> {
+    let start = previous
+        .iter()
+        .rposition(|x| {
+            x.is_pipe_operator() || x.is_and_operator() || x.is_or_operator() || x.is_not_operator()
+        })
+        .map(|x| x + 1)
+        .unwrap_or(0);
+
+    let mut words = &previous[start..];
+
+    if let Some(keyword) = words.first() {
+        if start == 0 && keyword.is_literal() && matches!(keyword.text(), "if" | "elif" | "while") {
+            words = &words[1..];
+        }
+    }
+
+    words.iter().map(|x| x.text().to_string()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shell::completion::Completions;
+    use crate::sources::user_function::UserFunction;
+
+    fn word(context: Context, start: usize, text: &str) -> Option<Word> {
+        Some(Word {
+            context,
+            start,
+            text: text.to_string(),
+        })
+    }
+
+    fn strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn argument(quote: Quote, words: &[&str]) -> Context {
+        let words = strings(words);
+        Context::Argument { quote, words }
+    }
+
+    #[test]
+    fn get_word_1() {
+        assert_eq!(word(Context::Command, 0, ""), get_word(""));
+        assert_eq!(word(Context::Command, 0, "ec"), get_word("ec"));
+        assert_eq!(word(Context::Command, 8, "gr"), get_word("cat x | gr"));
+        assert_eq!(word(Context::Command, 3, "te"), get_word("if te"));
+        assert_eq!(word(Context::Command, 9, ""), get_word("true and "));
+    }
+
+    #[test]
+    fn get_word_2() {
+        assert_eq!(word(Context::Variable, 6, "na"), get_word("echo {na"));
+        assert_eq!(word(Context::Environment, 7, "HO"), get_word("echo e{HO"));
+
+        let echo = |quote| argument(quote, &["echo"]);
+        assert_eq!(word(echo(Quote::Unquoted), 5, "sr"), get_word("echo sr"));
+        assert_eq!(word(echo(Quote::Unquoted), 5, ""), get_word("echo "));
+
+        let cat = argument(Quote::SingleQuoted, &["cat"]);
+        assert_eq!(word(cat, 4, "a b"), get_word("cat 'a b"));
+
+        let git = argument(Quote::Unquoted, &["git", "commit"]);
+        assert_eq!(word(git, 14, "--a"), get_word("if git commit --a"));
+    }
+
+    #[test]
+    fn get_word_3() {
+        let module = |loaded| Context::Module { loaded };
+        assert_eq!(
+            word(module(false), 18, "fi"),
+            get_word("self::load_module fi")
+        );
+        assert_eq!(word(module(true), 20, ""), get_word("self::unload_module "));
+    }
+
+    #[test]
+    fn complete_path_1() {
+        let dir = env::temp_dir().join(format!("smsh-complete-path-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("some file"), "").unwrap();
+        std::fs::write(dir.join(".hidden"), "").unwrap();
+
+        let text = format!("{}/s", dir.display());
+        let mut values = complete_path(&text, Quote::Unquoted, false);
+        values.sort();
+
+        assert_eq!(
+            vec![
+                format!("'{}/some file' ", dir.display()),
+                format!("{}/sub/", dir.display())
+            ],
+            values
+        );
+
+        let text = format!("{}/", dir.display());
+        assert_eq!(2, complete_path(&text, Quote::Unquoted, false).len());
+        assert_eq!(1, complete_path(&text, Quote::Unquoted, true).len());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn complete_argument_1() {
+        let mut completions = Completions::default();
+        completions.update(&strings(&["tool"]), |x| x.flags = strings(&["--verbose"]));
+        completions.update(&strings(&["tool", "deploy"]), |x| {
+            x.argument = Some(Argument::Words(strings(&["staging", "production"])))
+        });
+        completions.update(&strings(&["tool", "status"]), |_| {});
+
+        let mut names = Names {
+            completions,
+            ..Default::default()
+        };
+
+        let mut complete = |text, words: &[&str]| {
+            let mut values = complete_argument(text, Quote::Unquoted, &strings(words), &mut names);
+            values.sort();
+            values
+        };
+
+        assert_eq!(vec!["deploy ", "status "], complete("", &["tool"]));
+        assert_eq!(vec!["--verbose "], complete("--v", &["tool"]));
+        assert_eq!(vec!["production "], complete("p", &["tool", "deploy"]));
+        assert_eq!(
+            vec!["production "],
+            complete("p", &["tool", "deploy", "staging"])
+        );
+    }
+
+    #[test]
+    fn complete_argument_2() {
+        let mut completions = Completions::default();
+        completions.update(&strings(&["tool"]), |x| {
+            x.argument = Some(Argument::Function("targets".to_string()))
+        });
+
+        let body = vec!["echo staging production".to_string()];
+        let mut names = Names {
+            completions,
+            functions: vec![UserFunction::new("targets".to_string(), body)],
+            ..Default::default()
+        };
+
+        let words = strings(&["tool"]);
+        let values = complete_argument("s", Quote::Unquoted, &words, &mut names);
+        assert_eq!(vec!["staging "], values);
+
+        // The function's words are kept for the rest of the line
+        names.functions.clear();
+        let values = complete_argument("p", Quote::Unquoted, &words, &mut names);
+        assert_eq!(vec!["production "], values);
+    }
 }
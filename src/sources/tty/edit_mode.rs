@@ -0,0 +1,37 @@
+// Emacs-style editing, as reedline provides it, plus Tab and Shift-Tab,
+// which complete the word under the cursor and cycle through the
+// completions, and Alt-R, which searches the lines run in the current
+// directory.
+
+use reedline::{default_emacs_keybindings, Emacs, KeyCode, KeyModifiers, ReedlineEvent};
+
+use super::history::HERE_MENU;
+
+pub const COMPLETION_MENU: &str = "completion_menu";
+
+pub fn build_edit_mode() -> Emacs {
+    let mut keybindings = default_emacs_keybindings();
+
+    keybindings.add_binding(
+        KeyModifiers::NONE,
+        KeyCode::Tab,
+        ReedlineEvent::UntilFound(vec![
+            ReedlineEvent::Menu(COMPLETION_MENU.to_string()),
+            ReedlineEvent::MenuNext,
+        ]),
+    );
+
+    keybindings.add_binding(
+        KeyModifiers::SHIFT,
+        KeyCode::BackTab,
+        ReedlineEvent::MenuPrevious,
+    );
+
+    keybindings.add_binding(
+        KeyModifiers::ALT,
+        KeyCode::Char('r'),
+        ReedlineEvent::Menu(HERE_MENU.to_string()),
+    );
+
+    Emacs::new(keybindings)
+}
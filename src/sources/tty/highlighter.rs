@@ -0,0 +1,283 @@
+// Colors the line being edited as smsh would read it.  Each physical line
+// is tokenized as it stands; a line that cannot be, for an unmatched quote
+// or brace, is in error from the point at fault on.  Commands are colored
+// by what they resolve to, looked up as the line is typed.
+
+use nu_ansi_term::{Color, Style};
+use reedline::{Highlighter, StyledText};
+
+use crate::line::{
+    determine_line_type, get_tokens, is_redirection_operator, Construct, Diagnostic, Expansion,
+    LineType, Quote, Token,
+};
+use crate::shell::names::SharedNames;
+use crate::shell::Commands;
+
+use std::cell::RefCell;
+use std::ops::Range;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Class {
+    Keyword,
+    Operator,
+    Builtin,
+    Function,
+    Executable,
+    Missing, // A command that resolves to nothing
+    Quoted,
+    Expansion(Expansion),
+    Selection,
+    Error,
+}
+
+impl Class {
+    fn style(&self) -> Style {
+        match self {
+            Class::Keyword => Style::new().fg(Color::Purple).bold(),
+            Class::Operator => Style::new().fg(Color::Yellow).bold(),
+            Class::Builtin => Style::new().fg(Color::Cyan),
+            Class::Function => Style::new().fg(Color::Blue),
+            Class::Executable => Style::new().fg(Color::Green),
+            Class::Missing => Style::new().fg(Color::Red),
+            Class::Quoted => Style::new().fg(Color::Yellow),
+            Class::Expansion(Expansion::Variable) => Style::new().fg(Color::LightPurple),
+            Class::Expansion(Expansion::Environment) => Style::new().fg(Color::LightCyan),
+            Class::Expansion(Expansion::Subshell) => Style::new().fg(Color::LightBlue),
+            Class::Expansion(Expansion::Filename) => Style::new().fg(Color::LightGreen),
+            Class::Expansion(Expansion::Terminal) => Style::new().fg(Color::LightYellow),
+            Class::Expansion(Expansion::None) => Style::new(),
+            Class::Selection => Style::new().fg(Color::DarkGray),
+            Class::Error => Style::new().fg(Color::White).on(Color::Red),
+        }
+    }
+}
+
+pub struct SmshHighlighter {
+    names: SharedNames,
+    commands: RefCell<Commands>, // Resolutions of external commands
+}
+
+impl SmshHighlighter {
+    pub fn new(names: SharedNames) -> Self {
+        SmshHighlighter {
+            names,
+            commands: RefCell::new(Commands::new()),
+        }
+    }
+
+    // Functions come before builtins, and builtins before executables,
+    // as when the command is run
+    fn resolve(&self, command: &str) -> Class {
+        let names = self.names.lock();
+
+        if names.functions.iter().any(|x| x.name() == command) {
+            Class::Function
+        } else if names.builtins.iter().any(|x| x == command) {
+            Class::Builtin
+        } else if self.commands.borrow_mut().resolve(command).is_some() {
+            Class::Executable
+        } else {
+            Class::Missing
+        }
+    }
+}
+
+impl Highlighter for SmshHighlighter {
+    fn highlight(&self, line: &str, _cursor: usize) -> StyledText {
+        let mut styled_text = StyledText::new();
+
+        for physical_line in line.split_inclusive('\n') {
+            let mut end = 0; // Of the text styled so far
+
+            for (class, range) in classify(physical_line, |x| self.resolve(x)) {
+                if range.start > end {
+                    styled_text.push((Style::new(), physical_line[end..range.start].to_string()));
+                }
+
+                styled_text.push((class.style(), physical_line[range.clone()].to_string()));
+                end = range.end;
+            }
+
+            if end < physical_line.len() {
+                styled_text.push((Style::new(), physical_line[end..].to_string()));
+            }
+        }
+
+        styled_text
+    }
+}
+
+// The parts of `line` to be colored, in order.  What is not given is
+// left as it is.
+fn classify(line: &str, resolve: impl Fn(&str) -> Class) -> Vec<(Class, Range<usize>)> {
+    let line = line.trim_end_matches('\n');
+    let mut classes = Vec::<(Class, Range<usize>)>::new();
+
+    let (tokens, error) = match get_tokens(line) {
+        Ok(tokens) => (tokens, None),
+        Err(e) => {
+            let start = e
+                .downcast_ref::<Diagnostic>()
+                .map(|x| x.span().start)
+                .unwrap_or(0);
+
+            let tokens = get_tokens(&line[..start]).unwrap_or_default();
+            (tokens, Some(start..line.len()))
+        }
+    };
+
+    let line_type = match tokens.first() {
+        Some(token) => determine_line_type(token.text()),
+        None => LineType::Empty,
+    };
+
+    let mut command_position = true;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let range = token.span().start..token.span().end;
+
+        let class = match &line_type {
+            LineType::ShellConstruct(construct) if i == 0 => {
+                command_position = matches!(
+                    construct,
+                    Construct::If | Construct::Elif | Construct::While
+                );
+                Some(Class::Keyword)
+            }
+            LineType::ShellConstruct(Construct::For)
+                if i == 2 && token.is_literal() && token.text() == "in" =>
+            {
+                Some(Class::Keyword)
+            }
+            LineType::ShellConstruct(Construct::FunctionDefinition) if i == 1 => {
+                Some(Class::Function)
+            }
+            _ if token.is_and_operator() || token.is_or_operator() || token.is_not_operator() => {
+                command_position = true;
+                Some(Class::Keyword)
+            }
+            _ if token.is_pipe_operator() => {
+                command_position = true;
+                Some(Class::Operator)
+            }
+            _ if token.is_background_operator()
+                || token.is_here_doc_operator()
+                || is_redirection_operator(token) =>
+            {
+                Some(Class::Operator)
+            }
+            _ if command_position && token.is_literal() => {
+                command_position = false;
+                Some(resolve(token.text()))
+            }
+            _ => {
+                command_position = false;
+                word_class(token)
+            }
+        };
+
+        // A selection is colored apart from what it selects from
+        let selection = if token.has_selection() {
+            line[range.clone()].rfind('[').map(|x| range.start + x)
+        } else {
+            None
+        };
+
+        match (class, selection) {
+            (Some(class), Some(at)) => {
+                classes.push((class, range.start..at));
+                classes.push((Class::Selection, at..range.end));
+            }
+            (Some(class), None) => classes.push((class, range)),
+            (None, Some(at)) => classes.push((Class::Selection, at..range.end)),
+            (None, None) => {}
+        }
+    }
+
+    if let Some(range) = error {
+        classes.push((Class::Error, range));
+    }
+
+    classes
+}
+
+fn word_class(token: &Token) -> Option<Class> {
+    if token.expansion() != Expansion::None {
+        Some(Class::Expansion(token.expansion()))
+    } else if token.quote() != Quote::Unquoted {
+        Some(Class::Quoted)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn resolve(command: &str) -> Class {
+        match command {
+            "cd" => Class::Builtin,
+            "ls" | "grep" => Class::Executable,
+            _ => Class::Missing,
+        }
+    }
+
+    fn classes(line: &str) -> Vec<(Class, &str)> {
+        classify(line, resolve)
+            .into_iter()
+            .map(|(class, range)| (class, &line[range]))
+            .collect()
+    }
+
+    #[test]
+    fn classify_1() {
+        assert_eq!(
+            vec![
+                (Class::Executable, "ls"),
+                (Class::Expansion(Expansion::Variable), "{dir}"),
+                (Class::Operator, "|"),
+                (Class::Executable, "grep"),
+                (Class::Quoted, "'a b'"),
+                (Class::Operator, ">"),
+            ],
+            classes("ls {dir} | grep 'a b' > out")
+        );
+    }
+
+    #[test]
+    fn classify_2() {
+        assert_eq!(
+            vec![
+                (Class::Keyword, "if"),
+                (Class::Missing, "nope"),
+                (Class::Keyword, "and"),
+                (Class::Builtin, "cd"),
+                (Class::Expansion(Expansion::Environment), "e{HOME}"),
+                (Class::Selection, "[0]"),
+            ],
+            classes("if nope and cd e{HOME}[0]")
+        );
+
+        assert_eq!(
+            vec![
+                (Class::Keyword, "for"),
+                (Class::Keyword, "in"),
+                (Class::Expansion(Expansion::Filename), "f{*}"),
+            ],
+            classes("for x in f{*}")
+        );
+    }
+
+    #[test]
+    fn classify_3() {
+        assert_eq!(
+            vec![(Class::Executable, "ls"), (Class::Error, "'some file")],
+            classes("ls 'some file")
+        );
+        assert_eq!(
+            vec![(Class::Builtin, "cd"), (Class::Error, "{dir")],
+            classes("cd {dir")
+        );
+    }
+}
@@ -0,0 +1,148 @@
+// Suggests the rest of the line being typed, in gray, from the lines run
+// before in the current directory.  The right arrow fills in a hint; it
+// is run only once Enter is pressed, as with anything typed.
+
+use nu_ansi_term::{Color, Style};
+use reedline::Hinter;
+
+use crate::shell::history::{HistoryEntry, SharedHistory};
+
+use std::collections::HashMap;
+use std::env;
+
+pub struct SmshHinter {
+    history: SharedHistory,
+    style: Style,
+    current_hint: String,
+}
+
+impl SmshHinter {
+    pub fn new(history: SharedHistory) -> Self {
+        SmshHinter {
+            history,
+            style: Style::new().fg(Color::DarkGray),
+            current_hint: String::new(),
+        }
+    }
+}
+
+impl Hinter for SmshHinter {
+    // Reedline's own history is passed over for the shell's, which knows
+    // where each line was run
+    fn handle(
+        &mut self,
+        line: &str,
+        pos: usize,
+        _history: &dyn reedline::History,
+        use_ansi_coloring: bool,
+    ) -> String {
+        self.current_hint = if pos == line.len() {
+            let cwd = env::current_dir()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            let history = self.history.lock();
+            suggest(history.entries(), &cwd, line)
+                .map(|x| x[line.len()..].to_string())
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        if use_ansi_coloring && !self.current_hint.is_empty() {
+            self.style.paint(&self.current_hint).to_string()
+        } else {
+            self.current_hint.clone()
+        }
+    }
+
+    fn complete_hint(&self) -> String {
+        self.current_hint.clone()
+    }
+
+    // The hint up to the end of its next word
+    fn next_hint_token(&self) -> String {
+        let start = self.current_hint.len() - self.current_hint.trim_start().len();
+
+        match self.current_hint[start..].find(char::is_whitespace) {
+            Some(i) => self.current_hint[..start + i].to_string(),
+            None => self.current_hint.clone(),
+        }
+    }
+}
+
+// The command beginning with `line` that was run most in `cwd`, with
+// recent runs counting for more than old ones.  Commands run elsewhere
+// are suggested only when none run in `cwd` will do.
+fn suggest<'a>(entries: &'a [HistoryEntry], cwd: &str, line: &str) -> Option<&'a str> {
+    if line.trim().is_empty() {
+        return None;
+    }
+
+    let candidates: Vec<(usize, &HistoryEntry)> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.command.len() > line.len() && x.command.starts_with(line))
+        .collect();
+
+    let here: Vec<(usize, &HistoryEntry)> = candidates
+        .iter()
+        .filter(|(_, x)| x.cwd == cwd)
+        .cloned()
+        .collect();
+
+    let candidates = if here.is_empty() { candidates } else { here };
+
+    // Each run counts for less the more lines have been run since
+    let mut scores = HashMap::<&str, (f64, usize)>::new();
+
+    for (i, entry) in candidates {
+        let age = entries.len() - i;
+        let score = scores.entry(&entry.command).or_insert((0.0, i));
+        score.0 += 1.0 / age as f64;
+        score.1 = i; // The last run, should scores be even
+    }
+
+    scores
+        .into_iter()
+        .max_by(|(_, a), (_, b)| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+        .map(|(command, _)| command)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suggest_1() {
+        let lines = [
+            ("/a", "git status"),
+            ("/a", "git status"),
+            ("/b", "git log"),
+            ("/a", "git stash"),
+            ("/a", "make"),
+            ("/a", "make"),
+        ];
+
+        let entries: Vec<HistoryEntry> = lines
+            .iter()
+            .map(|(cwd, command)| HistoryEntry {
+                timestamp: 0,
+                cwd: cwd.to_string(),
+                status: Some(0),
+                duration: None,
+                command: command.to_string(),
+            })
+            .collect();
+        let entries = &entries;
+
+        // Run twice counts for more than run once since
+        assert_eq!(Some("git status"), suggest(entries, "/a", "git st"));
+        // Only lines run here, while there are any
+        assert_eq!(Some("git log"), suggest(entries, "/b", "git"));
+        assert_eq!(Some("git log"), suggest(entries, "/c", "git l"));
+        // Nothing past what was typed
+        assert_eq!(None, suggest(entries, "/a", "make"));
+        assert_eq!(None, suggest(entries, "/a", ""));
+    }
+}
@@ -0,0 +1,98 @@
+// Reedline keeps its own copy of the commands in history, for moving
+// through them and for Ctrl-R; it is rebuilt from the shell's history
+// whenever the two differ.  Alt-R opens a menu of the lines run in the
+// current directory, most recent first, found by the text typed since.
+
+use reedline::{Completer, FileBackedHistory, HistoryItem, Span, Suggestion};
+
+use crate::shell::history::{History, SharedHistory};
+
+use std::env;
+
+pub const HERE_MENU: &str = "history_here";
+
+pub fn build_line_history(history: &History) -> FileBackedHistory {
+    let mut line_history = FileBackedHistory::new(history.limit().max(1));
+
+    for entry in history.entries() {
+        let _ = reedline::History::save(
+            &mut line_history,
+            HistoryItem::from_command_line(&entry.command),
+        );
+    }
+
+    line_history
+}
+
+pub struct HereCompleter {
+    history: SharedHistory,
+}
+
+impl HereCompleter {
+    pub fn new(history: SharedHistory) -> Self {
+        HereCompleter { history }
+    }
+}
+
+impl Completer for HereCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        let cwd = env::current_dir()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let history = self.history.lock();
+        let mut commands = Vec::<&str>::new();
+
+        for entry in history.entries().iter().rev() {
+            if entry.cwd == cwd
+                && entry.command.contains(line)
+                && !commands.contains(&entry.command.as_str())
+            {
+                commands.push(&entry.command);
+            }
+        }
+
+        // The whole of what was typed since the menu opened is replaced
+        commands
+            .into_iter()
+            .map(|x| Suggestion {
+                value: x.to_string(),
+                description: None,
+                extra: None,
+                span: Span {
+                    start: pos,
+                    end: pos + line.len(),
+                },
+                append_whitespace: false,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn here_completer_1() {
+        let shared = SharedHistory::new(History::new(None));
+
+        for command in ["echo one", "ls", "echo two", "echo one"] {
+            shared.lock().start(command);
+            shared.lock().finish(0).unwrap();
+        }
+
+        let mut completer = HereCompleter::new(shared.clone());
+        let values: Vec<String> = completer
+            .complete("echo", 0)
+            .into_iter()
+            .map(|x| x.value)
+            .collect();
+
+        assert_eq!(vec!["echo one", "echo two"], values);
+
+        let line_history = build_line_history(&shared.lock());
+        let count = reedline::History::count_all(&line_history).unwrap();
+        assert_eq!(4, count);
+    }
+}
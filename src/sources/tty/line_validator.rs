@@ -1,3 +1,4 @@
+use crate::line::{determine_indentation, get_tokens};
 use crate::sources::is_complete;
 use reedline::{ValidationResult, Validator};
 
@@ -5,7 +6,9 @@ pub struct SmshLineValidator;
 
 impl Validator for SmshLineValidator {
     fn validate(&self, line: &str) -> ValidationResult {
-        if !is_complete(line) {
+        let (_, rest) = split_lines(line);
+
+        if !rest.is_empty() {
             // Every line/line+block must be complete
             ValidationResult::Incomplete
         } else if is_shell_construct(line) {
@@ -14,12 +17,54 @@ impl Validator for SmshLineValidator {
             } else {
                 ValidationResult::Incomplete
             }
+        } else if opens_here_doc(line.lines().next().unwrap_or("")) {
+            // A line indented no further than the here-document ends its
+            // body, and is taken on its own
+            if contains_finished_block(line) {
+                ValidationResult::Complete
+            } else if let Some(end) = here_doc_end(line) {
+                self.validate(&line[end..])
+            } else {
+                ValidationResult::Incomplete
+            }
         } else {
             ValidationResult::Complete
         }
     }
 }
 
+// Splits `buffer` into logical lines, yielding them and whatever trails
+// them incomplete.  The body of a here-document is taken line by line as
+// it stands, quotes and all, for as long as its lines are blank or
+// indented further than the line that opened it.
+pub fn split_lines(buffer: &str) -> (Vec<String>, String) {
+    let mut lines = Vec::<String>::new();
+    let mut line = String::new();
+    let mut here_doc_indent: Option<usize> = None; // Of the open here-document
+
+    for physical_line in buffer.split('\n') {
+        if let Some(indent) = here_doc_indent {
+            if physical_line.trim().is_empty() || determine_indentation(physical_line) > indent {
+                lines.push(physical_line.to_string());
+                continue;
+            }
+            here_doc_indent = None;
+        }
+
+        // Physical lines of length 0 are ignored
+        line.push_str(physical_line);
+
+        if !line.is_empty() && is_complete(&line) {
+            if opens_here_doc(&line) {
+                here_doc_indent = Some(determine_indentation(&line));
+            }
+            lines.push(std::mem::take(&mut line));
+        }
+    }
+
+    (lines, line)
+}
+
 // A block is complete if it ends with a line
 // of indentation 0
 fn contains_finished_block(line: &str) -> bool {
@@ -29,11 +74,77 @@ fn contains_finished_block(line: &str) -> bool {
         || line.ends_with("\n   \n")
 }
 
+fn opens_here_doc(line: &str) -> bool {
+    get_tokens(line).is_ok_and(|x| x.iter().any(|x| x.is_here_doc_operator()))
+}
+
+// Where the first line that is neither blank nor indented further than
+// the first line of `buffer` begins
+fn here_doc_end(buffer: &str) -> Option<usize> {
+    let mut physical_lines = buffer.split('\n');
+    let first_line = physical_lines.next()?;
+    let indent = determine_indentation(first_line);
+    let mut offset = first_line.len() + 1;
+
+    for physical_line in physical_lines {
+        if !physical_line.trim().is_empty() && determine_indentation(physical_line) <= indent {
+            return Some(offset);
+        }
+        offset += physical_line.len() + 1;
+    }
+
+    None
+}
+
 // TODO: Handle leading whitespace
 fn is_shell_construct(line: &str) -> bool {
+    // Dropping a function takes no block
+    if line.starts_with("fn --drop") {
+        return false;
+    }
+
     line.starts_with("if")
         || line.starts_with("for")
         || line.starts_with("while")
         || line.starts_with("case")
         || line.starts_with("fn")
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn is_valid(buffer: &str) -> bool {
+        matches!(
+            SmshLineValidator.validate(buffer),
+            ValidationResult::Complete
+        )
+    }
+
+    #[test]
+    fn validate_1() {
+        // Quotes in a here-document's body need not be balanced
+        assert!(!is_valid("python3 <<\n    # don't"));
+        assert!(is_valid("python3 <<\n    # don't\n\n"));
+        assert!(is_valid("sqlite3 db <<\n    SELECT 'it''s';\necho done"));
+        assert!(!is_valid("echo 'it\n"));
+    }
+
+    #[test]
+    fn validate_2() {
+        // Blank lines stay within the body, until a line is indented no further
+        assert!(!is_valid("cat <<\n    a\n\n    b"));
+        assert!(is_valid("cat <<\n    a\n\n    b\necho done"));
+        assert!(!is_valid("cat <<\n    a\n\n    b\nif true"));
+    }
+
+    #[test]
+    fn split_lines_1() {
+        let (lines, rest) = split_lines("cat <<\n    # don't\n\n    b\necho 'x\ny'");
+        assert_eq!(
+            vec!["cat <<", "    # don't", "", "    b", "echo 'xy'"],
+            lines
+        );
+        assert!(rest.is_empty());
+    }
+}
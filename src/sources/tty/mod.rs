@@ -1,45 +1,91 @@
 use std::borrow::Cow;
 use std::boxed::Box;
 use std::collections::VecDeque;
-use std::env::current_dir;
 
 use anyhow::Result;
-use crossterm::style::Stylize;
-use nix::unistd;
-use reedline::{Prompt, PromptEditMode, PromptHistorySearch, Reedline, Signal};
+use reedline::{
+    ColumnarMenu, ListMenu, Prompt, PromptEditMode, PromptHistorySearch, Reedline, ReedlineMenu,
+    Signal,
+};
 
-use super::{Source, SourceKind};
+use super::{PromptText, Source, SourceKind};
 use crate::line::Line;
-use crate::sources::is_complete;
+use crate::shell::default_prompt;
+use crate::shell::history::SharedHistory;
+use crate::shell::names::SharedNames;
 
 mod line_validator;
-use line_validator::SmshLineValidator;
+use line_validator::{split_lines, SmshLineValidator};
 mod completer;
-use completer::build_command_completer;
+use completer::SmshCompleter;
+mod highlighter;
+use highlighter::SmshHighlighter;
+mod hinter;
+use hinter::SmshHinter;
+mod edit_mode;
+use edit_mode::{build_edit_mode, COMPLETION_MENU};
+mod history;
+use history::{build_line_history, HereCompleter, HERE_MENU};
 
 pub struct Tty {
     line_editor: Reedline,
+    history: SharedHistory,
+    history_generation: Option<u64>, // Of the history last given to line_editor
     line_num: usize,
     last_line: Option<Line>,
     buffer: VecDeque<Line>,
+    prompt: SmshPrompt,
 }
 
 impl Tty {
-    pub fn new() -> Box<dyn Source> {
-        let line_editor = match build_command_completer() {
-            Ok(completer) => Reedline::create()
-                .with_validator(Box::new(SmshLineValidator))
-                .with_completer(Box::new(completer)),
-            Err(_) => Reedline::create().with_validator(Box::new(SmshLineValidator)),
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(history: SharedHistory, names: SharedNames) -> Box<dyn Source> {
+        let completion_menu = ReedlineMenu::EngineCompleter(Box::new(
+            ColumnarMenu::default().with_name(COMPLETION_MENU),
+        ));
+
+        let here_menu = ReedlineMenu::WithCompleter {
+            menu: Box::new(ListMenu::default().with_name(HERE_MENU)),
+            completer: Box::new(HereCompleter::new(history.clone())),
         };
 
+        let line_editor = Reedline::create()
+            .with_validator(Box::new(SmshLineValidator))
+            .with_highlighter(Box::new(SmshHighlighter::new(names.clone())))
+            .with_hinter(Box::new(SmshHinter::new(history.clone())))
+            .with_completer(Box::new(SmshCompleter::new(names)))
+            .with_edit_mode(Box::new(build_edit_mode()))
+            .with_menu(completion_menu)
+            .with_menu(here_menu);
+
         Box::new(Tty {
             line_editor,
+            history,
+            history_generation: None,
             line_num: 1, // TODO: line_num should probably reflect physical line, not logical...
             last_line: None,
             buffer: VecDeque::<Line>::new(),
+            prompt: SmshPrompt(PromptText {
+                left: default_prompt(),
+                right: String::new(),
+                multiline: "> ".to_string(),
+            }),
         })
     }
+
+    // Gives the line editor a fresh copy of the history if entries were
+    // removed from it, or if the editor holds a line that was not recorded
+    fn sync_history(&mut self) {
+        let history = self.history.lock();
+
+        if self.history_generation != Some(history.generation()) {
+            let line_history = build_line_history(&history);
+            self.history_generation = Some(history.generation());
+
+            let line_editor = std::mem::replace(&mut self.line_editor, Reedline::create());
+            self.line_editor = line_editor.with_history(Box::new(line_history));
+        }
+    }
 }
 
 impl Source for Tty {
@@ -49,8 +95,15 @@ impl Source for Tty {
             return Ok(Some(line));
         }
 
-        match self.line_editor.read_line(&SimplePrompt)? {
+        self.sync_history();
+
+        match self.line_editor.read_line(&self.prompt)? {
             Signal::Success(buffer) => {
+                // Reedline keeps any line that is not empty
+                if !self.history.lock().start(&buffer) && !buffer.is_empty() {
+                    self.history_generation = None;
+                }
+
                 // Since we want blocks to be given the Reedline multiline editing treatment,
                 // we must collect a block of lines in a single line (buffer), then decompose it, then
                 // serve it up later (using self.buffer).
@@ -59,18 +112,13 @@ impl Source for Tty {
                 // completeness tests (notably, is_complete(), found in sources/mod.rs).  Hence,
                 // we can assume that line is complete.  It may, however, contain multiple physical
                 // lines (or even a block).
-                let mut logical_lines = Vec::<String>::new();
-                let mut line = String::new();
-
                 // So, first we collect the logical lines in a vector...
-                for physical_line in buffer.split("\n") {
-                    // Implicitly removes newline characters
-                    line.push_str(physical_line); // Implicitly ignores physical lines of length 0
-
-                    if line.len() > 0 && is_complete(line.as_str()) {
-                        logical_lines.push(line);
-                        line = String::new();
-                    }
+                let (mut logical_lines, _) = split_lines(&buffer);
+
+                // A block is terminated by an empty line; without it, a construct
+                // at the end of the buffer would wait on the next prompt for its block's end.
+                if logical_lines.len() > 1 {
+                    logical_lines.push(String::new());
                 }
 
                 for line in logical_lines {
@@ -98,6 +146,14 @@ impl Source for Tty {
         SourceKind::Tty
     }
 
+    fn will_prompt(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    fn set_prompt(&mut self, prompt: PromptText) {
+        self.prompt = SmshPrompt(prompt);
+    }
+
     fn print_error(&mut self) -> Result<()> {
         if let Some(line) = &self.last_line {
             eprintln!("{}", line);
@@ -107,33 +163,16 @@ impl Source for Tty {
     }
 }
 
-struct SimplePrompt;
+// Rendered by the shell; see Shell::before_prompt
+struct SmshPrompt(PromptText);
 
-impl Prompt for SimplePrompt {
+impl Prompt for SmshPrompt {
     fn render_prompt_left(&self) -> Cow<'_, str> {
-        let mut prompt_string = String::new();
-
-        match current_dir() {
-            Ok(path) => {
-                if let Some(s) = path.to_str() {
-                    prompt_string.push_str(s);
-                }
-            }
-            Err(_) => {}
-        }
-
-        if unistd::getuid().is_root() {
-            prompt_string.push_str("# ");
-            prompt_string = prompt_string.red().to_string();
-        } else {
-            prompt_string.push_str("$ ");
-        };
-
-        Cow::Owned(prompt_string)
+        Cow::Borrowed(&self.0.left)
     }
 
     fn render_prompt_right(&self) -> Cow<'_, str> {
-        Cow::Owned("".to_string())
+        Cow::Borrowed(&self.0.right)
     }
 
     fn render_prompt_indicator(&self, _prompt_mode: PromptEditMode) -> Cow<'_, str> {
@@ -141,8 +180,7 @@ impl Prompt for SimplePrompt {
         Cow::Owned(prompt_string)
     }
     fn render_prompt_multiline_indicator(&self) -> Cow<'_, str> {
-        let prompt_string = "> ".to_string();
-        Cow::Owned(prompt_string)
+        Cow::Borrowed(&self.0.multiline)
     }
     fn render_prompt_history_search_indicator(
         &self,
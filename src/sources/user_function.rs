@@ -8,6 +8,7 @@ pub struct UserFunction {
     fn_name: String,
     fn_body: Vec<String>,
     line_num: usize,
+    arguments: Vec<String>, // Given upon invocation
 }
 
 impl UserFunction {
@@ -16,13 +17,20 @@ impl UserFunction {
             fn_name,
             fn_body,
             line_num: 0,
+            arguments: vec![],
         }
     }
 
-    pub fn build_source(self) -> Box<dyn Source> {
+    pub fn build_source(mut self, arguments: Vec<String>) -> Box<dyn Source> {
+        self.arguments = arguments;
         Box::new(self)
     }
 
+    pub fn with_name(mut self, fn_name: String) -> UserFunction {
+        self.fn_name = fn_name;
+        self
+    }
+
     pub fn name(&self) -> &str {
         &self.fn_name
     }
@@ -58,4 +66,8 @@ impl Source for UserFunction {
         );
         Ok(())
     }
+
+    fn arguments(&self) -> Option<Vec<String>> {
+        Some(self.arguments.clone())
+    }
 }
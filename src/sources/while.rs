@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Result};
+
+use super::{Interrupt, Source, SourceKind};
+use crate::line::{Line, LineID};
+
+// The shell tests the condition before each pass (see Source::condition),
+// and discards the loop once it is false.
+#[derive(Clone)]
+pub struct While {
+    condition: String,
+    body: Vec<Line>,
+    line_num: usize,
+    tested: bool,                  // The condition, for the current pass
+    passes: usize,                 // Tests of the condition so far
+    limit: Option<usize>,          // On passes, set by `while_limit`
+    done: bool,                    // Set by `break`
+    while_line_identifier: LineID, // Topmost line identifier
+}
+
+impl While {
+    pub fn new(
+        condition: String,
+        body: Vec<Line>,
+        limit: Option<usize>,
+        while_line_identifier: LineID,
+    ) -> Self {
+        Self {
+            condition,
+            body,
+            line_num: 0,
+            tested: false,
+            passes: 0,
+            limit,
+            done: false,
+            while_line_identifier,
+        }
+    }
+
+    pub fn build_source(self) -> Box<dyn Source> {
+        Box::new(self)
+    }
+}
+
+impl Source for While {
+    fn get_line(&mut self) -> Result<Option<Line>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        // The condition held once more than the limit allows
+        if let Some(limit) = self.limit.filter(|x| self.passes > *x) {
+            self.done = true;
+            return Err(anyhow!(
+                "while: Stopped after {} passes (see `while_limit`)",
+                limit
+            ));
+        }
+
+        // An empty line ends each pass, so that a block at the end of
+        // the body is not taken to continue into the next pass
+        if self.line_num == self.body.len() {
+            self.line_num = 0;
+            self.tested = false;
+
+            return Ok(Some(Line::new(
+                String::new(),
+                self.while_line_identifier.line_num,
+                self.while_line_identifier.source_kind.clone(),
+            )?));
+        }
+
+        let line = self.body[self.line_num].clone();
+        self.line_num += 1;
+
+        Ok(Some(line))
+    }
+
+    fn get_source_kind(&self) -> SourceKind {
+        self.while_line_identifier.source_kind.clone()
+    }
+
+    fn print_error(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Option<String> {
+        // At the limit, it is tested once more, to tell a loop that
+        // would go on from one that ends there
+        if self.tested || self.done {
+            return None;
+        }
+
+        self.tested = true;
+        self.passes += 1;
+
+        Some(self.condition.clone())
+    }
+
+    fn interrupt(&mut self, interrupt: Interrupt) -> bool {
+        match interrupt {
+            Interrupt::Break => self.done = true,
+            Interrupt::Continue => {
+                self.line_num = 0;
+                self.tested = false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_while(body: &[&str], limit: Option<usize>) -> While {
+        let body = body
+            .iter()
+            .map(|x| Line::new(x.to_string(), 2, SourceKind::Subshell).unwrap())
+            .collect();
+
+        let identifier = LineID {
+            source_kind: SourceKind::Subshell,
+            line_num: 1,
+        };
+
+        While::new("true".to_string(), body, limit, identifier)
+    }
+
+    fn next_text(source: &mut While) -> String {
+        source.get_line().unwrap().unwrap().raw_text().to_string()
+    }
+
+    #[test]
+    fn while_1() {
+        let mut source = new_while(&["    echo a", "    echo b"], Some(2));
+
+        for _ in 0..2 {
+            assert_eq!(Some("true".to_string()), source.condition());
+            assert_eq!(None, source.condition());
+            assert_eq!("    echo a", next_text(&mut source));
+            assert_eq!("    echo b", next_text(&mut source));
+            assert_eq!("", next_text(&mut source));
+        }
+
+        assert_eq!(Some("true".to_string()), source.condition());
+        assert!(source.get_line().is_err());
+        assert!(source.get_line().unwrap().is_none());
+    }
+
+    #[test]
+    fn while_2() {
+        let mut source = new_while(&["    echo a", "    echo b"], None);
+
+        source.condition();
+        assert_eq!("    echo a", next_text(&mut source));
+
+        assert!(source.interrupt(Interrupt::Continue));
+        assert!(source.condition().is_some());
+        assert_eq!("    echo a", next_text(&mut source));
+
+        assert!(source.interrupt(Interrupt::Break));
+        assert_eq!(None, source.condition());
+        assert!(source.get_line().unwrap().is_none());
+    }
+}